@@ -1,11 +1,11 @@
 use adw::prelude::*;
 use gtk::glib;
+use danmakw::formats::bilibili;
 use std::{
     cell::{Cell, RefCell},
     rc::Rc,
     time::Duration,
 };
-mod utils;
 
 pub fn build_ui(application: &gtk::Application) {
     let window = adw::ApplicationWindow::builder()
@@ -123,7 +123,7 @@ pub fn build_ui(application: &gtk::Application) {
         playback_clock,
         async move {
             glib::timeout_future_seconds(1).await;
-            let danmakus = utils::parse_bilibili_xml(include_str!("test.xml")).unwrap();
+            let danmakus = bilibili::parse_str(include_str!("test.xml")).unwrap();
             let max_time = danmakus.iter().map(|danmaku| danmaku.start).fold(0.0, f64::max);
 
             adj.set_upper(max_time.max(100.0));
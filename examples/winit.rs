@@ -1,4 +1,4 @@
-use danmakw::{DanmakuClock, Renderer};
+use danmakw::{DanmakuClock, Renderer, TrackLoader};
 use std::sync::Arc;
 use wgpu::{
     CompositeAlphaMode,
@@ -18,8 +18,6 @@ use winit::{
     window::Window,
 };
 
-mod utils;
-
 fn main() {
     let event_loop = EventLoop::new().unwrap();
     event_loop
@@ -86,9 +84,8 @@ impl WindowState {
 
         let mut renderer = danmakw::Renderer::new(&device, &queue, surface_format, scale_factor);
 
-        let danmakus = utils::parse_bilibili_xml(include_str!("test.xml")).unwrap();
-
-        renderer.init(danmakus);
+        // Parsed in the background, playback starts right away
+        renderer.load(TrackLoader::bilibili(include_bytes!("test.xml").as_slice()));
         renderer.set_font_name("Noto Sans".to_string());
 
         Self {
@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+
+use super::Danmaku;
+
+const WINDOW_MS: f64 = 1000.0;
+// Comments longer than this get no extra priority
+const LENGTH_CAP: usize = 20;
+
+// Caps how many danmaku are shown during busy scenes. Over the cap, the ones
+// shown are a weighted random sample of the next second, so a climax is
+// thinned out evenly instead of only showing whatever came first.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DensityLimiter {
+    max_per_second: Option<usize>,
+    max_on_screen: Option<usize>,
+    own_sender: Option<String>,
+    seed: u64,
+    // Times danmaku were let through within the last window
+    shown: VecDeque<f64>,
+}
+
+impl DensityLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_per_second(mut self, max: usize) -> Self {
+        self.max_per_second = Some(max);
+        self
+    }
+
+    pub fn max_on_screen(mut self, max: usize) -> Self {
+        self.max_on_screen = Some(max);
+        self
+    }
+
+    // Danmaku from this `DanmakuMeta::sender` are always shown
+    pub fn own_sender(mut self, sender: impl Into<String>) -> Self {
+        self.own_sender = Some(sender.into());
+        self
+    }
+
+    // The same seed picks the same danmaku for the same track
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    // Picks which of the due `candidates` to show at `time`. `upcoming` is the
+    // rest of the track in order, the danmaku due within the next second
+    // compete for the same budget if they will be `shown`, e.g. aren't
+    // filtered out or merged as repeats.
+    pub fn sample<'a>(
+        &mut self, candidates: Vec<&'a Danmaku>, upcoming: &[Danmaku], time: f64, on_screen: usize,
+        shown: impl Fn(&Danmaku) -> bool,
+    ) -> Vec<&'a Danmaku> {
+        // Also forgets the window after seeking either way
+        self.shown
+            .retain(|&shown| shown > time - WINDOW_MS && shown <= time);
+
+        let budget = [
+            self.max_per_second
+                .map(|max| max.saturating_sub(self.shown.len())),
+            self.max_on_screen.map(|max| max.saturating_sub(on_screen)),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+
+        let ahead = upcoming.partition_point(|danmaku| danmaku.start <= time + WINDOW_MS);
+        let ahead: Vec<&Danmaku> = upcoming[..ahead]
+            .iter()
+            .filter(|danmaku| shown(danmaku))
+            .collect();
+        let kept = match budget {
+            Some(budget) if candidates.len() + ahead.len() > budget => {
+                let mut keys: Vec<f64> = candidates
+                    .iter()
+                    .chain(&ahead)
+                    .map(|danmaku| self.key(danmaku))
+                    .collect();
+                let threshold = match budget.checked_sub(1) {
+                    Some(last) => *keys.select_nth_unstable_by(last, |a, b| b.total_cmp(a)).1,
+                    None => f64::INFINITY,
+                };
+
+                candidates
+                    .into_iter()
+                    .filter(|danmaku| self.key(danmaku) >= threshold)
+                    .collect()
+            }
+            _ => candidates,
+        };
+
+        self.shown.extend(kept.iter().map(|_| time));
+        kept
+    }
+
+    // Weighted sampling key, the highest keys are kept. A danmaku gets the same
+    // key whenever it is looked at, so peeking ahead agrees with what is shown.
+    fn key(&self, danmaku: &Danmaku) -> f64 {
+        if self.own_sender.is_some() && danmaku.meta.sender == self.own_sender {
+            return f64::INFINITY;
+        }
+
+        // Bilibili weights go from 0 to 10
+        let weight = 1.0 + danmaku.meta.weight.unwrap_or(0) as f64 / 10.0;
+        let length = danmaku.content.trim().chars().count().min(LENGTH_CAP);
+        let length = 0.5 + 0.5 * length as f64 / LENGTH_CAP as f64;
+
+        self.random(danmaku).powf(1.0 / (weight * length))
+    }
+
+    // Uniform in (0, 1], derived from the seed and the danmaku
+    fn random(&self, danmaku: &Danmaku) -> f64 {
+        let mut state = split_mix(self.seed ^ danmaku.start.to_bits());
+        for chunk in danmaku.content.as_bytes().chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            state = split_mix(state ^ u64::from_le_bytes(word));
+        }
+
+        ((state >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// SplitMix64 step
+fn split_mix(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::danmaku::{
+        FilterSet,
+        step_frames,
+    };
+
+    // 100 comments per second for two seconds, popped once per 50ms frame
+    fn play(limiter: &mut DensityLimiter, track: &[Danmaku]) -> Vec<String> {
+        let mut shown = Vec::new();
+        step_frames(track, 50.0, 40, |candidates, upcoming, time| {
+            let kept = limiter.sample(candidates, upcoming, time, 0, |_| true);
+            shown.extend(kept.iter().map(|danmaku| danmaku.content.clone()));
+        });
+        shown
+    }
+
+    fn climax() -> Vec<Danmaku> {
+        (0..200)
+            .map(|i| Danmaku::at(&format!("comment {i}"), i as f64 * 10.0))
+            .collect()
+    }
+
+    #[test]
+    fn test_per_second_cap() {
+        let mut limiter = DensityLimiter::new().max_per_second(20).seed(7);
+        let shown = play(&mut limiter, &climax());
+
+        assert!((30..=45).contains(&shown.len()), "{}", shown.len());
+        // Spread over the scene rather than the first ones only
+        let index = |content: &String| content[8..].parse::<usize>().unwrap();
+        assert!(shown.iter().any(|content| index(content) < 50));
+        assert!(shown.iter().any(|content| index(content) >= 150));
+
+        let mut again = DensityLimiter::new().max_per_second(20).seed(7);
+        assert_eq!(play(&mut again, &climax()), shown);
+        let mut other = DensityLimiter::new().max_per_second(20).seed(8);
+        assert_ne!(play(&mut other, &climax()), shown);
+    }
+
+    #[test]
+    fn test_priorities() {
+        let mut track = climax();
+        track[100].meta.sender = Some("me".to_string());
+        for danmaku in track.iter_mut().step_by(2) {
+            danmaku.meta.weight = Some(10);
+        }
+
+        let mut limiter = DensityLimiter::new().max_per_second(10).own_sender("me");
+        let shown = play(&mut limiter, &track);
+
+        assert!(shown.contains(&"comment 100".to_string()));
+        let heavy = shown
+            .iter()
+            .filter(|content| content[8..].parse::<usize>().unwrap() % 2 == 0)
+            .count();
+        assert!(heavy > shown.len() - heavy, "{heavy} of {}", shown.len());
+    }
+
+    #[test]
+    fn test_on_screen_cap() {
+        let track = climax();
+        let candidates: Vec<&Danmaku> = track[..5].iter().collect();
+        let mut limiter = DensityLimiter::new().max_on_screen(30);
+
+        let sample = |limiter: &mut DensityLimiter, upcoming, on_screen| {
+            limiter.sample(candidates.clone(), upcoming, 0.0, on_screen, |_| true)
+        };
+        assert_eq!(sample(&mut limiter, &[], 25).len(), 5);
+        assert!(sample(&mut limiter, &[], 30).is_empty());
+        assert!(sample(&mut limiter, &track[5..], 20).len() < 5);
+    }
+
+    #[test]
+    fn test_filtered_look_ahead() {
+        let track = climax();
+        // One in ten is left, 10 per second
+        let mut filters = FilterSet::new();
+        filters.add(|danmaku: &Danmaku| !danmaku.content.ends_with('0'));
+
+        let mut shown = |look_ahead_filtered: bool| {
+            let mut limiter = DensityLimiter::new().max_per_second(30);
+            let mut shown = 0;
+            step_frames(&track, 50.0, 40, |due, upcoming, time| {
+                let due = due.into_iter().filter(|d| filters.allows(d)).collect();
+                let kept = limiter.sample(due, upcoming, time, 0, |d| {
+                    !look_ahead_filtered || filters.allows_uncounted(d)
+                });
+                shown += kept.len();
+            });
+            shown
+        };
+
+        assert_eq!(shown(true), 20);
+        assert!(shown(false) < 20);
+    }
+}
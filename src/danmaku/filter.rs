@@ -0,0 +1,338 @@
+use std::collections::HashSet;
+
+use super::{
+    Color,
+    Danmaku,
+    DanmakuMode,
+};
+
+// Decides whether a danmaku is hidden before it reaches the screen
+pub trait DanmakuFilter {
+    fn blocks(&self, danmaku: &Danmaku) -> bool;
+}
+
+impl<F: Fn(&Danmaku) -> bool> DanmakuFilter for F {
+    fn blocks(&self, danmaku: &Danmaku) -> bool {
+        self(danmaku)
+    }
+}
+
+// Hides danmaku containing any of the words, ignoring case by default
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct KeywordFilter {
+    words: Vec<String>,
+    lowercase_words: Vec<String>,
+    case_sensitive: bool,
+}
+
+impl KeywordFilter {
+    pub fn new<S: Into<String>>(words: impl IntoIterator<Item = S>) -> Self {
+        let words: Vec<String> = words.into_iter().map(Into::into).collect();
+        Self {
+            lowercase_words: words.iter().map(|word| word.to_lowercase()).collect(),
+            words,
+            case_sensitive: false,
+        }
+    }
+
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+}
+
+impl DanmakuFilter for KeywordFilter {
+    fn blocks(&self, danmaku: &Danmaku) -> bool {
+        if self.case_sensitive {
+            return self
+                .words
+                .iter()
+                .any(|word| danmaku.content.contains(word.as_str()));
+        }
+
+        let content = danmaku.content.to_lowercase();
+        self.lowercase_words
+            .iter()
+            .any(|word| content.contains(word.as_str()))
+    }
+}
+
+// Hides danmaku matching any of the patterns
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone)]
+pub struct RegexFilter(regex::RegexSet);
+
+#[cfg(feature = "regex")]
+impl RegexFilter {
+    pub fn new<S: AsRef<str>>(patterns: impl IntoIterator<Item = S>) -> Result<Self, regex::Error> {
+        regex::RegexSet::new(patterns).map(Self)
+    }
+}
+
+#[cfg(feature = "regex")]
+impl DanmakuFilter for RegexFilter {
+    fn blocks(&self, danmaku: &Danmaku) -> bool {
+        self.0.is_match(&danmaku.content)
+    }
+}
+
+// Hides danmaku by `DanmakuMeta::sender`, e.g. Bilibili user hashes
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SenderFilter(pub HashSet<String>);
+
+impl SenderFilter {
+    pub fn new<S: Into<String>>(senders: impl IntoIterator<Item = S>) -> Self {
+        Self(senders.into_iter().map(Into::into).collect())
+    }
+}
+
+impl DanmakuFilter for SenderFilter {
+    fn blocks(&self, danmaku: &Danmaku) -> bool {
+        danmaku
+            .meta
+            .sender
+            .as_ref()
+            .is_some_and(|sender| self.0.contains(sender))
+    }
+}
+
+// The modes to hide
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ModeFilter {
+    pub scroll: bool,
+    pub reverse_scroll: bool,
+    pub top_center: bool,
+    pub bottom_center: bool,
+    pub positioned: bool,
+}
+
+impl DanmakuFilter for ModeFilter {
+    fn blocks(&self, danmaku: &Danmaku) -> bool {
+        match danmaku.mode {
+            DanmakuMode::Scroll => self.scroll,
+            DanmakuMode::ReverseScroll => self.reverse_scroll,
+            DanmakuMode::TopCenter => self.top_center,
+            DanmakuMode::BottomCenter => self.bottom_center,
+            DanmakuMode::Positioned(_) => self.positioned,
+        }
+    }
+}
+
+// Compares RGB only, alpha is ignored
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorFilter {
+    // Hides every other color, `Allow(vec![Color::default()])` keeps white only
+    Allow(Vec<Color>),
+    Block(Vec<Color>),
+}
+
+impl DanmakuFilter for ColorFilter {
+    fn blocks(&self, danmaku: &Danmaku) -> bool {
+        let rgb = |color: &Color| (color.r, color.g, color.b);
+        let listed =
+            |colors: &[Color]| colors.iter().any(|color| rgb(color) == rgb(&danmaku.color));
+
+        match self {
+            Self::Allow(colors) => !listed(colors),
+            Self::Block(colors) => listed(colors),
+        }
+    }
+}
+
+// Hides danmaku outside a length range, in characters of the trimmed text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LengthFilter {
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+}
+
+impl DanmakuFilter for LengthFilter {
+    fn blocks(&self, danmaku: &Danmaku) -> bool {
+        let len = danmaku.content.trim().chars().count();
+        self.min.is_some_and(|min| len < min) || self.max.is_some_and(|max| len > max)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FilterId(usize);
+
+struct Entry {
+    id: FilterId,
+    filter: Box<dyn DanmakuFilter>,
+    removed: u64,
+}
+
+// Filters applied in the order they were added. A hidden danmaku is counted
+// for the first filter that blocks it.
+#[derive(Default)]
+pub struct FilterSet {
+    entries: Vec<Entry>,
+    next_id: usize,
+}
+
+impl FilterSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, filter: impl DanmakuFilter + 'static) -> FilterId {
+        let id = FilterId(self.next_id);
+        self.next_id += 1;
+
+        self.entries.push(Entry {
+            id,
+            filter: Box::new(filter),
+            removed: 0,
+        });
+        id
+    }
+
+    // Swaps a filter in place, keeping its counter
+    pub fn replace(&mut self, id: FilterId, filter: impl DanmakuFilter + 'static) -> bool {
+        let Some(entry) = self.entries.iter_mut().find(|entry| entry.id == id) else {
+            return false;
+        };
+        entry.filter = Box::new(filter);
+        true
+    }
+
+    pub fn remove(&mut self, id: FilterId) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.id != id);
+        self.entries.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Danmaku hidden by this filter so far
+    pub fn removed(&self, id: FilterId) -> u64 {
+        self.entries
+            .iter()
+            .find(|entry| entry.id == id)
+            .map_or(0, |entry| entry.removed)
+    }
+
+    pub fn total_removed(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.removed).sum()
+    }
+
+    pub fn reset_counts(&mut self) {
+        for entry in &mut self.entries {
+            entry.removed = 0;
+        }
+    }
+
+    // Same as `allows` without counting, e.g. for danmaku that are only replayed
+    pub fn allows_uncounted(&self, danmaku: &Danmaku) -> bool {
+        !self
+            .entries
+            .iter()
+            .any(|entry| entry.filter.blocks(danmaku))
+    }
+
+    pub fn allows(&mut self, danmaku: &Danmaku) -> bool {
+        let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.filter.blocks(danmaku))
+        else {
+            return true;
+        };
+
+        entry.removed += 1;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed<'a>(filters: &mut FilterSet, danmaku: &'a [Danmaku]) -> Vec<&'a str> {
+        danmaku
+            .iter()
+            .filter(|danmaku| filters.allows(danmaku))
+            .map(|danmaku| danmaku.content.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_builtin_filters() {
+        let mut red = Danmaku::at("red", 0.0);
+        red.color = Color::from_rgb(0xFF0000);
+        let mut top = Danmaku::at("top", 0.0);
+        top.mode = DanmakuMode::TopCenter;
+        let mut spammer = Danmaku::at("hello", 0.0);
+        spammer.meta.sender = Some("abc123".to_string());
+        let track = [
+            Danmaku::at("Big SPOILER ahead", 0.0),
+            red,
+            top,
+            spammer,
+            Danmaku::at("w", 0.0),
+            Danmaku::at("ok", 0.0),
+        ];
+
+        let mut filters = FilterSet::new();
+        let keywords = filters.add(KeywordFilter::new(["spoiler"]));
+        let colors = filters.add(ColorFilter::Allow(vec![Color::default()]));
+        let modes = filters.add(ModeFilter {
+            top_center: true,
+            ..Default::default()
+        });
+        let senders = filters.add(SenderFilter::new(["abc123"]));
+        let length = filters.add(LengthFilter {
+            min: Some(2),
+            max: None,
+        });
+
+        assert_eq!(allowed(&mut filters, &track), ["ok"]);
+        for id in [keywords, colors, modes, senders, length] {
+            assert_eq!(filters.removed(id), 1);
+        }
+        assert_eq!(filters.total_removed(), 5);
+
+        assert!(!filters.allows_uncounted(&track[0]));
+        assert!(filters.allows_uncounted(&track[5]));
+        assert_eq!(filters.total_removed(), 5);
+    }
+
+    #[test]
+    fn test_runtime_changes() {
+        let track = [Danmaku::at("Spoiler", 0.0), Danmaku::at("fine", 0.0)];
+        let mut filters = FilterSet::new();
+        let id = filters.add(KeywordFilter::new(["Spoiler"]).case_sensitive(true));
+        assert_eq!(allowed(&mut filters, &track), ["fine"]);
+
+        assert!(filters.replace(id, |danmaku: &Danmaku| danmaku.content == "fine"));
+        assert_eq!(allowed(&mut filters, &track), ["Spoiler"]);
+        assert_eq!(filters.removed(id), 2);
+
+        filters.reset_counts();
+        assert!(filters.remove(id));
+        assert!(!filters.remove(id));
+        assert_eq!(allowed(&mut filters, &track), ["Spoiler", "fine"]);
+        assert_eq!(filters.removed(id), 0);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex() {
+        let track = [
+            Danmaku::at("2333333", 0.0),
+            Danmaku::at("23", 0.0),
+            Danmaku::at("hahaha", 0.0),
+        ];
+        let mut filters = FilterSet::new();
+        filters.add(RegexFilter::new([r"^23{3,}$", r"(ha){3}"]).unwrap());
+
+        assert_eq!(allowed(&mut filters, &track), ["23"]);
+        assert!(RegexFilter::new(["("]).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use super::{
+    Danmaku,
+    DanmakuQueue,
+    sort::{
+        SortByTime,
+        merge_by_time,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId(usize);
+
+struct Source {
+    // Already shifted by the offset and tagged
+    danmaku: Vec<Danmaku>,
+    enabled: bool,
+}
+
+// Combines tracks from several sites or uploads into one. Sources added
+// earlier win when the same comment shows up in more than one of them.
+#[derive(Default)]
+pub struct TrackMerger {
+    sources: Vec<Source>,
+    dedupe_window: Option<f64>,
+}
+
+impl TrackMerger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Drops a comment when an earlier source has the same text within
+    // `window_ms`. Repeats inside a single source are kept.
+    pub fn dedupe(mut self, window_ms: f64) -> Self {
+        self.dedupe_window = Some(window_ms);
+        self
+    }
+
+    // `offset_ms` is added to every start time, e.g. to skip a longer intro.
+    // The tag ends up in `DanmakuMeta::source`.
+    pub fn add_source(
+        &mut self, mut danmaku: Vec<Danmaku>, offset_ms: f64, tag: Option<&str>,
+    ) -> SourceId {
+        for danmaku in &mut danmaku {
+            danmaku.start += offset_ms;
+            if let Some(tag) = tag {
+                danmaku.meta.source = Some(tag.to_string());
+            }
+        }
+        danmaku.sort_by_time();
+
+        self.sources.push(Source {
+            danmaku,
+            enabled: true,
+        });
+        SourceId(self.sources.len() - 1)
+    }
+
+    pub fn set_enabled(&mut self, id: SourceId, enabled: bool) {
+        if let Some(source) = self.sources.get_mut(id.0) {
+            source.enabled = enabled;
+        }
+    }
+
+    pub fn is_enabled(&self, id: SourceId) -> bool {
+        self.sources.get(id.0).is_some_and(|source| source.enabled)
+    }
+
+    // The enabled sources as one track, sorted by time
+    pub fn merged(&self) -> Vec<Danmaku> {
+        // Start times of the kept comments per text, sorted
+        let mut seen: HashMap<&str, Vec<f64>> = HashMap::new();
+        let mut merged = Vec::new();
+
+        for source in self.sources.iter().filter(|source| source.enabled) {
+            let Some(window) = self.dedupe_window else {
+                merged = merge_by_time(merged, source.danmaku.clone());
+                continue;
+            };
+
+            let kept: Vec<&Danmaku> = source
+                .danmaku
+                .iter()
+                .filter(|danmaku| {
+                    let Some(starts) = seen.get(danmaku.content.trim()) else {
+                        return true;
+                    };
+                    let from = starts.partition_point(|&start| start < danmaku.start - window);
+                    starts.get(from).is_none_or(|&start| start > danmaku.start + window)
+                })
+                .collect();
+
+            for danmaku in &kept {
+                let starts = seen.entry(danmaku.content.trim()).or_default();
+                let index = starts.partition_point(|&start| start <= danmaku.start);
+                starts.insert(index, danmaku.start);
+            }
+
+            merged = merge_by_time(merged, kept.into_iter().cloned().collect());
+        }
+
+        merged
+    }
+
+    // Puts the merged track into the queue, keeping its position
+    pub fn apply(&self, queue: &mut DanmakuQueue) {
+        queue.replace(self.merged());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(danmaku: &[Danmaku]) -> Vec<(&str, f64)> {
+        danmaku.iter().map(|d| (d.content.as_str(), d.start)).collect()
+    }
+
+    #[test]
+    fn test_offsets_and_tags() {
+        let mut merger = TrackMerger::new();
+        merger.add_source(
+            vec![Danmaku::at("a", 1000.0), Danmaku::at("c", 3000.0)],
+            0.0,
+            None,
+        );
+        merger.add_source(vec![Danmaku::at("b", 500.0)], 1500.0, Some("nico"));
+
+        let merged = merger.merged();
+        assert_eq!(track(&merged), [("a", 1000.0), ("b", 2000.0), ("c", 3000.0)]);
+        assert_eq!(merged[0].meta.source, None);
+        assert_eq!(merged[1].meta.source.as_deref(), Some("nico"));
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let mut merger = TrackMerger::new().dedupe(500.0);
+        merger.add_source(
+            vec![Danmaku::at("hi", 1000.0), Danmaku::at("hi", 1200.0)],
+            0.0,
+            None,
+        );
+        merger.add_source(
+            vec![
+                Danmaku::at(" hi ", 1400.0),
+                Danmaku::at("hi", 1800.0),
+                Danmaku::at("other", 1000.0),
+            ],
+            0.0,
+            None,
+        );
+
+        assert_eq!(
+            track(&merger.merged()),
+            [("hi", 1000.0), ("other", 1000.0), ("hi", 1200.0), ("hi", 1800.0)]
+        );
+    }
+
+    #[test]
+    fn test_toggle_keeps_position() {
+        let mut merger = TrackMerger::new();
+        let first = merger.add_source(
+            vec![Danmaku::at("a", 100.0), Danmaku::at("c", 300.0)],
+            0.0,
+            None,
+        );
+        let second = merger.add_source(
+            vec![Danmaku::at("b", 200.0), Danmaku::at("d", 400.0)],
+            0.0,
+            None,
+        );
+
+        let mut queue = DanmakuQueue::new();
+        merger.apply(&mut queue);
+        assert_eq!(track(queue.pop_to_time(250.0).as_slice()), [("a", 100.0), ("b", 200.0)]);
+
+        merger.set_enabled(second, false);
+        assert!(merger.is_enabled(first));
+        assert!(!merger.is_enabled(second));
+        merger.apply(&mut queue);
+        assert_eq!(track(queue.pop_to_time(500.0).as_slice()), [("c", 300.0)]);
+
+        merger.set_enabled(second, true);
+        merger.apply(&mut queue);
+        queue.reset_time(0.0);
+        assert_eq!(queue.pop_to_time(500.0).len(), 4);
+    }
+}
@@ -0,0 +1,32 @@
+use std::collections::BTreeMap;
+
+// Where a danmaku came from, none of this affects rendering
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct DanmakuMeta {
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub id: Option<u64>,
+    // Anonymised sender, e.g. Bilibili's CRC32 user hash
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub sender: Option<String>,
+    // Unix timestamp in seconds
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub send_time: Option<i64>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub pool: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub weight: Option<u8>,
+    // Tag of the track it was merged from, see `TrackMerger`
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub source: Option<String>,
+    // Source specific fields that have no dedicated slot
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "BTreeMap::is_empty"))]
+    pub extras: BTreeMap<String, String>,
+}
+
+impl DanmakuMeta {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
@@ -1,24 +1,137 @@
+mod density;
+mod filter;
+mod merge;
+mod meta;
+mod motion;
 mod queue;
+mod repeat;
 mod sort;
 
+pub use density::DensityLimiter;
+#[cfg(feature = "regex")]
+pub use filter::RegexFilter;
+pub use filter::{
+    ColorFilter,
+    DanmakuFilter,
+    FilterId,
+    FilterSet,
+    KeywordFilter,
+    LengthFilter,
+    ModeFilter,
+    SenderFilter,
+};
+pub use merge::{
+    SourceId,
+    TrackMerger,
+};
+pub use meta::DanmakuMeta;
+pub use motion::{
+    AlphaKeyframe,
+    MotionSpec,
+    PathPoint,
+};
 pub use queue::DanmakuQueue;
+pub use repeat::RepeatMerger;
 
-use glyphon::Buffer;
+use std::{
+    fmt,
+    str::FromStr,
+};
 
-#[derive(Debug, Clone, PartialEq)]
+use glyphon::{
+    Buffer,
+    CustomGlyph,
+};
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Danmaku {
     pub content: String,
     // milliseconds
     pub start: f64,
     pub color: Color,
     pub mode: DanmakuMode,
+    // Font size relative to the standard size, `None` means 1.0
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub size: Option<f32>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "DanmakuMeta::is_empty"))]
+    pub meta: DanmakuMeta,
+    // Styled segments used for shaping instead of `content`,
+    // `content` still holds the plain text
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub spans: Option<Vec<TextSpan>>,
+    // How many identical comments were merged into this one, see `RepeatMerger`
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub repeats: Option<u32>,
+}
+
+impl Danmaku {
+    // 1.0 when the size is unset or can't be drawn
+    pub fn scale(&self) -> f32 {
+        self.size.filter(|&size| is_valid_size(size)).unwrap_or(1.0)
+    }
+
+    pub fn with_spans(mut self, spans: Vec<TextSpan>) -> Self {
+        self.content = spans.iter().map(|span| span.text.as_str()).collect();
+        self.spans = Some(spans);
+        self
+    }
+
+    // Number of renderer rows the danmaku occupies
+    pub fn row_span(&self) -> usize {
+        (self.scale().ceil() as usize).max(1)
+    }
+}
+
+// Zero, negative and non-finite sizes break text shaping, parsers drop them
+pub(crate) fn is_valid_size(size: f32) -> bool {
+    size.is_finite() && size > 0.0
+}
+
+pub const SCROLL_DURATION_MS: f32 = 8000.0;
+pub const CENTER_DURATION_MS: f32 = 5000.0;
+
+// How long danmaku stay on screen, in milliseconds. Sources disagree on this,
+// e.g. Niconico scrolls a comment across in 4 seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackDurations {
+    pub scroll: f32,
+    pub center: f32,
+}
+
+impl Default for TrackDurations {
+    fn default() -> Self {
+        Self {
+            scroll: SCROLL_DURATION_MS,
+            center: CENTER_DURATION_MS,
+        }
+    }
+}
+
+// A styled run of text, unset fields fall back to the danmaku's own style
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct TextSpan {
+    pub text: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub color: Option<Color>,
+    // CSS style weight, 400 is normal and 700 is bold
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub weight: Option<u16>,
+    pub italic: bool,
 }
 
 pub struct ScrollingDanmaku {
     pub danmaku: Danmaku,
     pub buffer: Buffer,
+    pub custom_glyphs: Vec<CustomGlyph>,
     pub x: f32,
     pub row: usize,
+    pub rows: usize,
     pub velocity_x: f32,
     pub width: f32,
 }
@@ -26,16 +139,38 @@ pub struct ScrollingDanmaku {
 pub struct CenterDanmaku {
     pub danmaku: Danmaku,
     pub buffer: Buffer,
+    pub custom_glyphs: Vec<CustomGlyph>,
     pub width: f32,
     pub row: usize,
+    pub rows: usize,
     pub remaining_time: f32,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionedDanmaku {
+    pub danmaku: Danmaku,
+    pub buffer: Buffer,
+    pub custom_glyphs: Vec<CustomGlyph>,
+    pub width: f32,
+    // milliseconds since the danmaku appeared
+    pub elapsed: f32,
+    // fractions of the viewport
+    pub x: f32,
+    pub y: f32,
+    pub alpha: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum DanmakuMode {
+    #[default]
     Scroll,
+    // Left to right
+    ReverseScroll,
     TopCenter,
     BottomCenter,
+    // Drawn at absolute coordinates, following the motion spec
+    Positioned(Box<MotionSpec>),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -45,3 +180,155 @@ pub struct Color {
     pub b: u8,
     pub a: u8,
 }
+
+impl Default for Color {
+    fn default() -> Self {
+        Self {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        }
+    }
+}
+
+impl Color {
+    // 0xRRGGBB, as used by most danmaku sources
+    pub fn from_rgb(rgb: u32) -> Self {
+        Self {
+            r: ((rgb >> 16) & 0xFF) as u8,
+            g: ((rgb >> 8) & 0xFF) as u8,
+            b: (rgb & 0xFF) as u8,
+            a: 255,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("Invalid color '{0}', expected #RRGGBB or #RRGGBBAA")]
+pub struct ParseColorError(pub String);
+
+// #RRGGBBAA
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}{:02X}", self.r, self.g, self.b, self.a)
+    }
+}
+
+// #RRGGBB or #RRGGBBAA
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseColorError(s.to_string());
+
+        let hex = s.strip_prefix('#').ok_or_else(error)?;
+        if !hex.is_ascii() || !matches!(hex.len(), 6 | 8) {
+            return Err(error());
+        }
+
+        let channel = |index: usize| {
+            hex.get(index * 2..index * 2 + 2)
+                .map_or(Ok(255), |channel| u8::from_str_radix(channel, 16))
+                .map_err(|_| error())
+        };
+
+        Ok(Self {
+            r: channel(0)?,
+            g: channel(1)?,
+            b: channel(2)?,
+            a: channel(3)?,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Color {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Color {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// Fixtures shared by the tests of every module
+#[cfg(test)]
+impl Danmaku {
+    pub(crate) fn at(content: &str, start: f64) -> Self {
+        Self {
+            content: content.to_string(),
+            start,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn with_mode(mut self, mode: DanmakuMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+// Pops a sorted `track` once per frame like the renderer does, handing
+// `frame` the danmaku due, the rest of the track and the time
+#[cfg(test)]
+pub(crate) fn step_frames<'a>(
+    track: &'a [Danmaku], frame_ms: f64, frames: usize,
+    mut frame: impl FnMut(Vec<&'a Danmaku>, &'a [Danmaku], f64),
+) {
+    let mut from = 0;
+    for index in 1..=frames {
+        let time = index as f64 * frame_ms;
+        let to = track.partition_point(|danmaku| danmaku.start <= time);
+        frame(track[from..to].iter().collect(), &track[to..], time);
+        from = to;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_hex_round_trip() {
+        let color = Color {
+            r: 0x12,
+            g: 0xAB,
+            b: 0x00,
+            a: 0x80,
+        };
+        assert_eq!(color.to_string(), "#12AB0080");
+        assert_eq!("#12ab0080".parse(), Ok(color));
+        assert_eq!("#FF0000".parse(), Ok(Color::from_rgb(0xFF0000)));
+    }
+
+    #[test]
+    fn test_scale() {
+        let sized = |size| Danmaku {
+            size,
+            ..Default::default()
+        };
+        assert_eq!(sized(Some(1.5)).scale(), 1.5);
+        for size in [
+            None,
+            Some(0.0),
+            Some(-5.0),
+            Some(f32::NAN),
+            Some(f32::INFINITY),
+        ] {
+            assert_eq!(sized(size).scale(), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_invalid_color() {
+        for s in ["FF0000", "#FF00", "#GG0000", "#FF0000F", "#ＦＦ0000"] {
+            assert_eq!(s.parse::<Color>(), Err(ParseColorError(s.to_string())));
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// Motion of a positioned (Bilibili mode 7 style) danmaku.
+// Coordinates are fractions of the viewport, times are milliseconds since
+// the danmaku appeared.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MotionSpec {
+    // How long the danmaku stays on screen
+    pub duration: f32,
+    // Linear movement between the points, the first one is used before it starts
+    pub path: Vec<PathPoint>,
+    pub alpha: Vec<AlphaKeyframe>,
+    // Degrees, not rendered yet
+    pub rotate_z: f32,
+    pub rotate_y: f32,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub font: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PathPoint {
+    pub time: f32,
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AlphaKeyframe {
+    pub time: f32,
+    pub alpha: f32,
+}
+
+impl MotionSpec {
+    pub fn position_at(&self, time: f32) -> (f32, f32) {
+        match interpolate(&self.path, time, |point| point.time) {
+            Some((from, to, t)) => (lerp(from.x, to.x, t), lerp(from.y, to.y, t)),
+            None => (0.0, 0.0),
+        }
+    }
+
+    pub fn alpha_at(&self, time: f32) -> f32 {
+        match interpolate(&self.alpha, time, |key| key.time) {
+            Some((from, to, t)) => lerp(from.alpha, to.alpha, t),
+            None => 1.0,
+        }
+    }
+}
+
+// Finds the keyframes around `time` and how far between them it is,
+// clamping to the first and last keyframe.
+fn interpolate<T>(keys: &[T], time: f32, time_of: impl Fn(&T) -> f32) -> Option<(&T, &T, f32)> {
+    let first = keys.first()?;
+    let next = keys.partition_point(|key| time_of(key) <= time);
+
+    if next == 0 {
+        return Some((first, first, 0.0));
+    }
+
+    let from = &keys[next - 1];
+    let Some(to) = keys.get(next) else {
+        return Some((from, from, 0.0));
+    };
+
+    let span = time_of(to) - time_of(from);
+    let t = if span > 0.0 {
+        (time - time_of(from)) / span
+    } else {
+        1.0
+    };
+
+    Some((from, to, t))
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> MotionSpec {
+        MotionSpec {
+            duration: 4000.0,
+            path: vec![
+                PathPoint {
+                    time: 1000.0,
+                    x: 0.1,
+                    y: 0.2,
+                },
+                PathPoint {
+                    time: 3000.0,
+                    x: 0.5,
+                    y: 0.6,
+                },
+            ],
+            alpha: vec![
+                AlphaKeyframe {
+                    time: 0.0,
+                    alpha: 1.0,
+                },
+                AlphaKeyframe {
+                    time: 4000.0,
+                    alpha: 0.0,
+                },
+            ],
+            rotate_z: 0.0,
+            rotate_y: 0.0,
+            font: None,
+        }
+    }
+
+    fn approx_eq(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-5 && (a.1 - b.1).abs() < 1e-5
+    }
+
+    #[test]
+    fn test_position_waits_for_delay() {
+        assert!(approx_eq(spec().position_at(0.0), (0.1, 0.2)));
+        assert!(approx_eq(spec().position_at(1000.0), (0.1, 0.2)));
+    }
+
+    #[test]
+    fn test_position_is_linear() {
+        assert!(approx_eq(spec().position_at(2000.0), (0.3, 0.4)));
+        assert!(approx_eq(spec().position_at(2500.0), (0.4, 0.5)));
+    }
+
+    #[test]
+    fn test_position_stops_at_end() {
+        assert!(approx_eq(spec().position_at(3000.0), (0.5, 0.6)));
+        assert!(approx_eq(spec().position_at(3900.0), (0.5, 0.6)));
+    }
+
+    #[test]
+    fn test_alpha_fades() {
+        let spec = spec();
+        assert_eq!(spec.alpha_at(0.0), 1.0);
+        assert_eq!(spec.alpha_at(1000.0), 0.75);
+        assert_eq!(spec.alpha_at(5000.0), 0.0);
+    }
+
+    #[test]
+    fn test_empty_keyframes() {
+        let spec = MotionSpec {
+            path: Vec::new(),
+            alpha: Vec::new(),
+            ..spec()
+        };
+        assert_eq!(spec.position_at(100.0), (0.0, 0.0));
+        assert_eq!(spec.alpha_at(100.0), 1.0);
+    }
+}
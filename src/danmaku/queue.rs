@@ -1,11 +1,24 @@
+use std::{
+    mem,
+    slice,
+};
+
 use super::{
     Danmaku,
-    sort::SortByTime,
+    sort::{
+        SortByTime,
+        merge_by_time,
+    },
 };
 
+// The track stays sorted in one place, a cursor marks what has been popped,
+// so seeking is a binary search instead of a copy of the track.
 pub struct DanmakuQueue {
-    now_queue: Vec<Danmaku>,
-    all_queue: Vec<Danmaku>,
+    track: Vec<Danmaku>,
+    // `track[..cursor]` has been popped
+    cursor: usize,
+    // Everything at or before this has been popped
+    time: f64,
 }
 
 impl Default for DanmakuQueue {
@@ -17,30 +30,243 @@ impl Default for DanmakuQueue {
 impl DanmakuQueue {
     pub fn new() -> Self {
         Self {
-            now_queue: Vec::new(),
-            all_queue: Vec::new(),
+            track: Vec::new(),
+            cursor: 0,
+            time: f64::NEG_INFINITY,
+        }
+    }
+
+    // Takes over a track that is already sorted by time without sorting it again
+    pub fn from_sorted(track: Vec<Danmaku>) -> Self {
+        debug_assert!(track.is_sorted_by(|a, b| a.start.total_cmp(&b.start).is_le()));
+        Self {
+            track,
+            ..Self::new()
         }
     }
 
     pub fn init(&mut self, danmaku: Vec<Danmaku>, time: f64) {
-        self.all_queue = danmaku;
-        self.all_queue.sort_by_time();
-        self.now_queue = self.all_queue.clone();
-        self.pop_to_time(time);
+        self.track = danmaku;
+        self.track.sort_by_time();
+        self.reset_time(time);
+    }
+
+    // Merges a batch in any order into the track, e.g. while it is still
+    // being loaded. Danmaku that are already in the past only show up after
+    // seeking back.
+    pub fn extend(&mut self, mut batch: Vec<Danmaku>) {
+        batch.sort_by_time();
+
+        let past = batch.partition_point(|danmaku| danmaku.start <= self.time);
+        self.track = merge_by_time(mem::take(&mut self.track), batch);
+        self.cursor += past;
+    }
+
+    // Adds a single danmaku after any with the same start time. Returns
+    // false if it is already in the past and won't be popped.
+    pub fn insert(&mut self, danmaku: Danmaku) -> bool {
+        let index = self
+            .track
+            .partition_point(|other| other.start <= danmaku.start);
+        let upcoming = danmaku.start > self.time;
+
+        self.track.insert(index, danmaku);
+        if !upcoming {
+            self.cursor += 1;
+        }
+        upcoming
+    }
+
+    // Removes every danmaku with this id, returns how many there were
+    pub fn remove(&mut self, id: u64) -> usize {
+        self.retain(|danmaku| danmaku.meta.id != Some(id))
     }
 
-    // When the time is changed, this should be called to update the queue
-    pub fn pop_to_time(&mut self, time: f64) -> Vec<Danmaku> {
-        let split_index = self
-            .now_queue
-            .partition_point(|danmaku| danmaku.start <= time);
+    // Keeps the danmaku the predicate holds for, returns how many were removed
+    pub fn retain(&mut self, mut keep: impl FnMut(&Danmaku) -> bool) -> usize {
+        let len = self.track.len();
+        let mut index = 0;
+        let mut removed_popped = 0;
+
+        self.track.retain(|danmaku| {
+            let kept = keep(danmaku);
+            if !kept && index < self.cursor {
+                removed_popped += 1;
+            }
+            index += 1;
+            kept
+        });
 
-        self.now_queue.drain(..split_index).collect()
+        self.cursor -= removed_popped;
+        len - self.track.len()
     }
 
+    // Swaps the whole track without replaying what is already in the past
+    pub fn replace(&mut self, danmaku: Vec<Danmaku>) {
+        let time = self.time;
+        self.init(danmaku, time);
+    }
+
+    // When the time is changed, this should be called to update the queue.
+    // Going back in time pops nothing until the time catches up again, use
+    // `reset_time` to replay.
+    pub fn pop_to_time(&mut self, time: f64) -> slice::Iter<'_, Danmaku> {
+        let from = self.cursor;
+        if time > self.time {
+            self.time = time;
+            self.cursor += self.track[from..].partition_point(|danmaku| danmaku.start <= time);
+        }
+
+        self.track[from..self.cursor].iter()
+    }
+
+    // The whole track, sorted by time
+    pub fn danmaku(&self) -> &[Danmaku] {
+        &self.track
+    }
+
+    // Everything at or before the current time, including what was skipped
+    pub fn past(&self) -> &[Danmaku] {
+        &self.track[..self.cursor]
+    }
+
+    // What `pop_to_time` will return next, sorted by time
+    pub fn upcoming(&self) -> &[Danmaku] {
+        &self.track[self.cursor..]
+    }
+
+    // Skips everything at or before `time` without popping it
     pub fn reset_time(&mut self, time: f64) {
-        self.now_queue = self.all_queue.clone();
+        self.time = time;
+        self.cursor = self.track.partition_point(|danmaku| danmaku.start <= time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents<'a>(danmaku: impl IntoIterator<Item = &'a Danmaku>) -> Vec<&'a str> {
+        danmaku
+            .into_iter()
+            .map(|danmaku| danmaku.content.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_extend_merges_sorted() {
+        let mut queue = DanmakuQueue::new();
+        queue.extend(vec![Danmaku::at("c", 300.0), Danmaku::at("a", 100.0)]);
+        queue.extend(vec![Danmaku::at("d", 400.0), Danmaku::at("b", 200.0)]);
+
+        assert_eq!(contents(queue.danmaku()), ["a", "b", "c", "d"]);
+        assert_eq!(contents(queue.pop_to_time(250.0)), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_extend_skips_past_danmaku() {
+        let mut queue = DanmakuQueue::new();
+        queue.init(vec![Danmaku::at("a", 100.0), Danmaku::at("d", 400.0)], 0.0);
+        assert_eq!(contents(queue.pop_to_time(250.0)), ["a"]);
+
+        queue.extend(vec![Danmaku::at("b", 200.0), Danmaku::at("c", 300.0)]);
+        assert_eq!(contents(queue.pop_to_time(500.0)), ["c", "d"]);
+
+        queue.reset_time(0.0);
+        assert_eq!(contents(queue.pop_to_time(500.0)), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_extend_keeps_order_of_equal_times() {
+        let mut queue = DanmakuQueue::new();
+        queue.extend(vec![Danmaku::at("a", 100.0)]);
+        queue.extend(vec![Danmaku::at("b", 100.0)]);
+
+        assert_eq!(contents(queue.danmaku()), ["a", "b"]);
+    }
+
+    fn track() -> Vec<Danmaku> {
+        vec![
+            Danmaku::at("c", 300.0),
+            Danmaku::at("a", 100.0),
+            Danmaku::at("b", 200.0),
+            Danmaku::at("b2", 200.0),
+            Danmaku::at("d", 400.0),
+        ]
+    }
+
+    #[test]
+    fn test_backward_seek() {
+        let mut queue = DanmakuQueue::new();
+        queue.init(track(), 0.0);
+        assert_eq!(contents(queue.pop_to_time(350.0)), ["a", "b", "b2", "c"]);
+
+        // Small steps back pop nothing and don't repeat once time moves on
+        assert!(queue.pop_to_time(250.0).as_slice().is_empty());
+        assert!(queue.pop_to_time(350.0).as_slice().is_empty());
+
+        queue.reset_time(150.0);
+        assert_eq!(contents(queue.pop_to_time(300.0)), ["b", "b2", "c"]);
+        queue.reset_time(f64::NEG_INFINITY);
+        assert_eq!(contents(queue.pop_to_time(100.0)), ["a"]);
+    }
+
+    #[test]
+    fn test_forward_seek() {
+        let mut queue = DanmakuQueue::new();
+        queue.init(track(), 200.0);
+        assert_eq!(contents(queue.pop_to_time(300.0)), ["c"]);
+
+        queue.reset_time(399.0);
+        assert_eq!(contents(queue.past()), ["a", "b", "b2", "c"]);
+        assert_eq!(contents(queue.upcoming()), ["d"]);
+        assert_eq!(contents(queue.pop_to_time(1000.0)), ["d"]);
+        queue.reset_time(5000.0);
+        assert!(queue.pop_to_time(6000.0).as_slice().is_empty());
+
+        queue.replace(track());
+        assert!(queue.pop_to_time(7000.0).as_slice().is_empty());
+        assert_eq!(queue.danmaku().len(), 5);
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut queue = DanmakuQueue::new();
+        queue.init(track(), 0.0);
+        assert_eq!(contents(queue.pop_to_time(250.0)), ["a", "b", "b2"]);
+
+        assert!(!queue.insert(Danmaku::at("sent", 250.0)));
+        assert!(queue.insert(Danmaku::at("b3", 260.0)));
+        assert!(!queue.insert(Danmaku::at("a2", 100.0)));
+        assert_eq!(contents(queue.pop_to_time(300.0)), ["b3", "c"]);
+
+        queue.reset_time(0.0);
+        assert_eq!(
+            contents(queue.pop_to_time(300.0)),
+            ["a", "a2", "b", "b2", "sent", "b3", "c"]
+        );
+    }
+
+    #[test]
+    fn test_remove_and_retain() {
+        let mut track = track();
+        for (id, danmaku) in track.iter_mut().enumerate() {
+            danmaku.meta.id = Some(id as u64);
+        }
+        track[4].meta.id = Some(0);
+
+        let mut queue = DanmakuQueue::new();
+        queue.init(track, 0.0);
+        assert_eq!(contents(queue.pop_to_time(250.0)), ["a", "b", "b2"]);
+
+        // "c" and "d" share an id
+        assert_eq!(queue.remove(0), 2);
+        assert_eq!(queue.remove(0), 0);
+        assert_eq!(queue.retain(|danmaku| danmaku.content != "b"), 1);
+        assert_eq!(contents(queue.danmaku()), ["a", "b2"]);
+        assert!(queue.pop_to_time(1000.0).as_slice().is_empty());
 
-        self.pop_to_time(time);
+        queue.reset_time(0.0);
+        assert_eq!(contents(queue.pop_to_time(1000.0)), ["a", "b2"]);
     }
 }
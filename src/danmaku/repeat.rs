@@ -0,0 +1,401 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+};
+
+use super::Danmaku;
+
+// Collapses floods of the same comment ("2333", "awsl") into one danmaku
+// with `Danmaku::repeats` set. The first one due is kept and counts the
+// repeats coming up within the window, those are skipped once they are due.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepeatMerger {
+    window_ms: f64,
+    normalize: bool,
+    max_scale: Option<f32>,
+    // Text of the kept danmaku, with the start and end of its window
+    active: HashMap<String, (f64, f64)>,
+    // Text of the kept live danmaku, with the danmaku, its count so far and
+    // the end of its window
+    live: HashMap<String, (Danmaku, u32, f64)>,
+}
+
+impl RepeatMerger {
+    pub fn new(window_ms: f64) -> Self {
+        Self {
+            window_ms,
+            normalize: true,
+            max_scale: None,
+            active: HashMap::new(),
+            live: HashMap::new(),
+        }
+    }
+
+    // Whether "ＡＷＳＬ", "awsl" and " awsl " count as the same text, on by default
+    pub fn normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    // Grows the font with the count, up to `max_scale` times the size
+    pub fn grow(mut self, max_scale: f32) -> Self {
+        self.max_scale = Some(max_scale);
+        self
+    }
+
+    // Merges the `due` danmaku at `time`. `upcoming` is the rest of the track
+    // in order, used to count the repeats ahead. Only the upcoming ones that
+    // are `allowed` count, e.g. the ones the filters will let through.
+    pub fn merge<'a>(
+        &mut self, due: Vec<&'a Danmaku>, upcoming: &[Danmaku], time: f64,
+        allowed: impl Fn(&Danmaku) -> bool,
+    ) -> Vec<Cow<'a, Danmaku>> {
+        // Also forgets the windows after seeking back
+        self.active.retain(|_, &mut (start, _)| start <= time);
+
+        // Floods are what this is for, so each text is normalized once per
+        // frame. The starts of the due danmaku and the ones ahead within the
+        // last window are grouped by text in order, next to how many of the
+        // due ones were gone through.
+        let keys: Vec<String> = due
+            .iter()
+            .map(|danmaku| self.key(&danmaku.content))
+            .collect();
+        let ahead = due.last().map_or(0, |last| {
+            upcoming.partition_point(|danmaku| danmaku.start <= last.start + self.window_ms)
+        });
+        let ahead: Vec<(String, f64)> = upcoming[..ahead]
+            .iter()
+            .filter(|danmaku| allowed(danmaku))
+            .map(|danmaku| (self.key(&danmaku.content), danmaku.start))
+            .collect();
+        let mut starts: HashMap<&str, (usize, Vec<f64>)> = HashMap::new();
+        let due_starts = keys
+            .iter()
+            .zip(&due)
+            .map(|(key, danmaku)| (key, danmaku.start));
+        for (key, start) in due_starts.chain(ahead.iter().map(|(key, start)| (key, *start))) {
+            starts.entry(key).or_default().1.push(start);
+        }
+
+        let mut merged = Vec::new();
+        for (danmaku, key) in due.into_iter().zip(&keys) {
+            let (seen, starts) = starts
+                .get_mut(key.as_str())
+                .expect("every due key was added");
+            *seen += 1;
+            if self.covers(key, danmaku.start) {
+                continue;
+            }
+
+            let end = danmaku.start + self.window_ms;
+            let repeats = starts[*seen..].partition_point(|&start| start <= end);
+
+            self.active.insert(key.clone(), (danmaku.start, end));
+            merged.push(self.with_count(danmaku, repeats as u32 + 1));
+        }
+
+        // Only now, a repeat counted near the end of a window may be due on
+        // the first frame after it
+        self.active.retain(|_, &mut (_, end)| time <= end);
+        merged
+    }
+
+    // Live danmaku can't be counted ahead, so a repeat arriving within the
+    // window of the first one adds to its count instead. Windows start at
+    // `time`, when the first one arrived, its start is set to that. Returns
+    // the danmaku to show and the ones shown earlier whose count went up.
+    pub fn merge_live(&mut self, arrived: Vec<Danmaku>, time: f64) -> (Vec<Danmaku>, Vec<Danmaku>) {
+        self.live
+            .retain(|_, (first, _, end)| first.start <= time && time <= *end);
+
+        let mut opened = Vec::new();
+        let mut counted = Vec::new();
+        for mut danmaku in arrived {
+            let key = self.key(&danmaku.content);
+            if let Some((_, count, _)) = self.live.get_mut(&key) {
+                *count += 1;
+                if !opened.contains(&key) && !counted.contains(&key) {
+                    counted.push(key);
+                }
+                continue;
+            }
+
+            danmaku.start = time;
+            self.live
+                .insert(key.clone(), (danmaku, 1, time + self.window_ms));
+            opened.push(key);
+        }
+
+        let with_counts = |keys: Vec<String>| {
+            keys.iter()
+                .map(|key| {
+                    let (first, count, _) = &self.live[key];
+                    self.with_count(first, *count).into_owned()
+                })
+                .collect()
+        };
+        (with_counts(opened), with_counts(counted))
+    }
+
+    // Whether the danmaku falls in the window of one merged before it, so it
+    // won't be shown
+    pub fn absorbs(&self, danmaku: &Danmaku) -> bool {
+        self.covers(&self.key(&danmaku.content), danmaku.start)
+    }
+
+    // Drops the window `danmaku` opened when it wasn't shown after all, e.g.
+    // the density limit left it out. Its repeats are then shown by themselves
+    // instead of counting up a danmaku nobody sees.
+    pub fn forget(&mut self, danmaku: &Danmaku) {
+        let key = self.key(&danmaku.content);
+        if self
+            .active
+            .get(&key)
+            .is_some_and(|&(start, _)| start == danmaku.start)
+        {
+            self.active.remove(&key);
+        }
+        if self
+            .live
+            .get(&key)
+            .is_some_and(|(first, ..)| first.start == danmaku.start)
+        {
+            self.live.remove(&key);
+        }
+    }
+
+    fn covers(&self, key: &str, start: f64) -> bool {
+        self.active.get(key).is_some_and(|&(_, end)| start <= end)
+    }
+
+    fn with_count<'a>(&self, danmaku: &'a Danmaku, count: u32) -> Cow<'a, Danmaku> {
+        if count == 1 {
+            return Cow::Borrowed(danmaku);
+        }
+
+        let mut danmaku = danmaku.clone();
+        danmaku.repeats = Some(count);
+        if let Some(max_scale) = self.max_scale {
+            let growth = (1.0 + 0.25 * (count as f32).log2()).min(max_scale.max(1.0));
+            danmaku.size = Some(danmaku.scale() * growth);
+        }
+        Cow::Owned(danmaku)
+    }
+
+    fn key(&self, content: &str) -> String {
+        if !self.normalize {
+            return content.to_string();
+        }
+
+        content
+            .split_whitespace()
+            .flat_map(|word| word.chars().chain([' ']))
+            .map(fold_width)
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+}
+
+// Fullwidth ASCII, as typed with CJK input methods, to halfwidth
+fn fold_width(c: char) -> char {
+    match c {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        DropPolicy,
+        LiveSource,
+        danmaku::{
+            DensityLimiter,
+            FilterSet,
+            SenderFilter,
+            step_frames,
+        },
+    };
+
+    // Pops the track once per 100ms frame
+    fn play(merger: &mut RepeatMerger, track: &[Danmaku]) -> Vec<(String, Option<u32>)> {
+        let mut shown = Vec::new();
+        step_frames(track, 100.0, 100, |due, upcoming, time| {
+            for danmaku in merger.merge(due, upcoming, time, |_| true) {
+                shown.push((danmaku.content.clone(), danmaku.repeats));
+            }
+        });
+        shown
+    }
+
+    #[test]
+    fn test_merge_within_window() {
+        let track = [
+            Danmaku::at("2333", 0.0),
+            Danmaku::at("2333", 50.0),
+            Danmaku::at("other", 120.0),
+            Danmaku::at("2333", 900.0),
+            Danmaku::at("2333", 1500.0),
+            Danmaku::at("2333", 1600.0),
+        ];
+        let mut merger = RepeatMerger::new(1000.0);
+
+        assert_eq!(
+            play(&mut merger, &track),
+            [
+                ("2333".to_string(), Some(3)),
+                ("other".to_string(), None),
+                ("2333".to_string(), Some(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repeat_due_after_window() {
+        let track = [Danmaku::at("2333", 0.0), Danmaku::at("2333", 995.0)];
+        let mut merger = RepeatMerger::new(1000.0);
+
+        // At 60 fps the repeat is due on the frame at 1000.2
+        let mut shown = Vec::new();
+        step_frames(&track, 1000.2 / 60.0, 120, |due, upcoming, time| {
+            for danmaku in merger.merge(due, upcoming, time, |_| true) {
+                shown.push((danmaku.start, danmaku.repeats));
+            }
+        });
+
+        assert_eq!(shown, [(0.0, Some(2))]);
+    }
+
+    #[test]
+    fn test_forget_dropped() {
+        let track = [
+            Danmaku::at("2333", 0.0),
+            Danmaku::at("2333", 300.0),
+            Danmaku::at("2333", 600.0),
+        ];
+        let mut merger = RepeatMerger::new(1000.0);
+        let mut limiter = DensityLimiter::new().max_on_screen(1);
+
+        let mut shown = Vec::new();
+        step_frames(&track, 100.0, 10, |due, upcoming, time| {
+            let merged = merger.merge(due, upcoming, time, |_| true);
+            let merged: Vec<&Danmaku> = merged.iter().map(AsRef::as_ref).collect();
+            // The screen is full at first
+            let on_screen = if time < 200.0 { 1 } else { 0 };
+            let kept = limiter.sample(merged.clone(), upcoming, time, on_screen, |danmaku| {
+                !merger.absorbs(danmaku)
+            });
+
+            for danmaku in merged {
+                if kept.iter().any(|&kept| std::ptr::eq(kept, danmaku)) {
+                    shown.push((danmaku.start, danmaku.repeats));
+                } else {
+                    merger.forget(danmaku);
+                }
+            }
+        });
+
+        assert_eq!(shown, [(300.0, Some(2))]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let track = [
+            Danmaku::at("AWSL", 0.0),
+            Danmaku::at(" ａｗｓｌ ", 10.0),
+            Danmaku::at("a w s l", 20.0),
+        ];
+
+        let mut merger = RepeatMerger::new(1000.0);
+        assert_eq!(play(&mut merger, &track).len(), 2);
+
+        let mut exact = RepeatMerger::new(1000.0).normalize(false);
+        assert_eq!(play(&mut exact, &track).len(), 3);
+    }
+
+    #[test]
+    fn test_grow() {
+        let track: Vec<Danmaku> = (0..16).map(|i| Danmaku::at("awsl", i as f64)).collect();
+        let due: Vec<&Danmaku> = track.iter().collect();
+
+        let mut merger = RepeatMerger::new(1000.0).grow(1.5);
+        let merged = merger.merge(due.clone(), &[], 100.0, |_| true);
+        assert_eq!(merged[0].repeats, Some(16));
+        assert_eq!(merged[0].size, Some(1.5));
+
+        // Seeking back starts counting again
+        let mut merger = RepeatMerger::new(1000.0).grow(4.0);
+        assert_eq!(
+            merger.merge(due.clone(), &[], 100.0, |_| true)[0].size,
+            Some(2.0)
+        );
+        assert!(
+            merger
+                .merge(due[..1].to_vec(), &[], 100.0, |_| true)
+                .is_empty()
+        );
+        assert_eq!(
+            merger.merge(due[..1].to_vec(), &[], -10.0, |_| true).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_blocked_repeats_not_counted() {
+        let mut track: Vec<Danmaku> = (0..4)
+            .map(|i| Danmaku::at("2333", i as f64 * 100.0))
+            .collect();
+        track[2].meta.sender = Some("spammer".to_string());
+        let mut filters = FilterSet::new();
+        filters.add(SenderFilter::new(["spammer"]));
+
+        let mut merger = RepeatMerger::new(1000.0);
+        let mut counts = Vec::new();
+        step_frames(&track, 100.0, 10, |due, upcoming, time| {
+            let due = due.into_iter().filter(|d| filters.allows(d)).collect();
+            let merged = merger.merge(due, upcoming, time, |d| filters.allows_uncounted(d));
+            counts.extend(merged.iter().map(|danmaku| danmaku.repeats));
+        });
+
+        assert_eq!(counts, [Some(3)]);
+        assert_eq!(filters.total_removed(), 1);
+    }
+
+    #[test]
+    fn test_live_repeats() {
+        let (sender, source) = LiveSource::bounded(8, DropPolicy::Block);
+        let mut merger = RepeatMerger::new(1000.0);
+        let mut shown = Vec::new();
+        let mut counted = Vec::new();
+
+        // "2333" keeps arriving once per 100ms frame, their start is ignored
+        for frame in 0..15 {
+            sender.send(Danmaku::at("2333", 99999.0)).unwrap();
+            if frame == 0 {
+                sender.send(Danmaku::at("2333", 99999.0)).unwrap();
+                sender.send(Danmaku::at("other", 99999.0)).unwrap();
+            }
+
+            let arrived = source.drain().collect();
+            let (new, bumped) = merger.merge_live(arrived, frame as f64 * 100.0);
+            shown.extend(new.into_iter().map(|d| (d.content, d.start, d.repeats)));
+            counted.extend(bumped.into_iter().map(|d| (d.start, d.repeats)));
+        }
+
+        assert_eq!(
+            shown,
+            [
+                ("2333".to_string(), 0.0, Some(2)),
+                ("other".to_string(), 0.0, None),
+                ("2333".to_string(), 1100.0, None),
+            ]
+        );
+        assert_eq!(counted.len(), 13);
+        assert_eq!(counted[9], (0.0, Some(12)));
+        assert_eq!(counted[12], (1100.0, Some(4)));
+    }
+}
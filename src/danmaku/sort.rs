@@ -10,3 +10,31 @@ impl SortByTime for Vec<Danmaku> {
         self.sort_by(|a, b| a.start.total_cmp(&b.start));
     }
 }
+
+// Merges two sorted lists, keeping `a` first for equal times
+pub fn merge_by_time(a: Vec<Danmaku>, b: Vec<Danmaku>) -> Vec<Danmaku> {
+    match (a.last(), b.first()) {
+        (None, _) => return b,
+        (_, None) => return a,
+        // Batches mostly arrive in order
+        (Some(last), Some(first)) if last.start <= first.start => {
+            let mut a = a;
+            a.extend(b);
+            return a;
+        }
+        _ => {}
+    }
+
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let mut a = a.into_iter().peekable();
+    let mut b = b.into_iter().peekable();
+
+    while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+        let next = if y.start < x.start { b.next() } else { a.next() };
+        merged.extend(next);
+    }
+
+    merged.extend(a);
+    merged.extend(b);
+    merged
+}
@@ -0,0 +1,804 @@
+//! Advanced SubStation Alpha export, for burning danmaku into videos, and
+//! import of the ASS files other danmaku converters produce.
+//! Rows are allocated by [`crate::layout_track`], so the result matches what
+//! the renderer shows for the same settings.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{
+        self,
+        BufRead,
+        Write,
+    },
+};
+
+use thiserror::Error;
+
+use crate::{
+    Color,
+    Danmaku,
+    DanmakuMode,
+    LayoutOptions,
+    MotionSpec,
+    PathPoint,
+    PlacedDanmaku,
+    Placement,
+    TextMeasure,
+    danmaku::is_valid_size,
+    layout_track,
+};
+
+const STYLE_NAME: &str = "Danmaku";
+
+pub fn write<W: Write>(
+    mut writer: W, danmaku: &[Danmaku], options: &LayoutOptions, measure: &mut impl TextMeasure,
+) -> io::Result<()> {
+    writer.write_all(header(options).as_bytes())?;
+
+    for placed in layout_track(danmaku, options, measure) {
+        writeln!(writer, "{}", event(&placed, options))?;
+    }
+
+    writer.flush()
+}
+
+pub fn to_string(
+    danmaku: &[Danmaku], options: &LayoutOptions, measure: &mut impl TextMeasure,
+) -> String {
+    let mut ass = header(options);
+
+    for placed in layout_track(danmaku, options, measure) {
+        ass.push_str(&event(&placed, options));
+        ass.push('\n');
+    }
+
+    ass
+}
+
+fn header(options: &LayoutOptions) -> String {
+    format!(
+        "[Script Info]\n\
+         ScriptType: v4.00+\n\
+         PlayResX: {width}\n\
+         PlayResY: {height}\n\
+         ScaledBorderAndShadow: yes\n\
+         WrapStyle: 2\n\
+         \n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, \
+         BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, \
+         BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: {STYLE_NAME},{font},{size},&H00FFFFFF,&H00FFFFFF,&H00000000,&H80000000,\
+         0,0,0,0,100,100,0,0,1,1,0,7,0,0,0,1\n\
+         \n\
+         [Events]\n\
+         Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        width = options.width.round(),
+        height = options.height.round(),
+        font = options.font_name,
+        size = options.font_size.round(),
+    )
+}
+
+fn event(placed: &PlacedDanmaku, options: &LayoutOptions) -> String {
+    let danmaku = placed.danmaku;
+    let mut tags = String::new();
+    let mut layer = 0;
+
+    match placed.placement {
+        Placement::Scroll { row, .. } => {
+            let y = options.row_top(row);
+            push_move(&mut tags, (options.width, y), (-placed.width, y), None);
+        }
+        Placement::ReverseScroll { row, .. } => {
+            let y = options.row_top(row);
+            push_move(&mut tags, (-placed.width, y), (options.width, y), None);
+        }
+        Placement::TopCenter { row, .. } => {
+            let x = (options.width - placed.width) / 2.0;
+            let _ = write!(tags, "\\pos({},{})", px(x), px(options.row_top(row)));
+        }
+        Placement::BottomCenter { row, rows } => {
+            let x = (options.width - placed.width) / 2.0;
+            let y = options.bottom_row_top(row, rows);
+            let _ = write!(tags, "\\pos({},{})", px(x), px(y));
+        }
+        Placement::Positioned => layer = 1,
+    }
+
+    let Color { r, g, b, a } = danmaku.color;
+    if (r, g, b) != (255, 255, 255) {
+        let _ = write!(tags, "\\c&H{b:02X}{g:02X}{r:02X}&");
+    }
+
+    if let DanmakuMode::Positioned(spec) = &danmaku.mode {
+        let point = |point: Option<&PathPoint>| {
+            point.map_or((0.0, 0.0), |point| {
+                (point.x * options.width, point.y * options.height)
+            })
+        };
+        let (first, last) = (spec.path.first(), spec.path.last());
+
+        if spec.path.len() > 1 {
+            let times = first.zip(last).map(|(first, last)| (first.time, last.time));
+            push_move(&mut tags, point(first), point(last), times);
+        } else {
+            let (x, y) = point(first);
+            let _ = write!(tags, "\\pos({},{})", px(x), px(y));
+        }
+
+        match (spec.alpha.first(), spec.alpha.last()) {
+            (Some(from), Some(to)) if from.alpha != to.alpha => {
+                let _ = write!(
+                    tags,
+                    "\\alpha&H{:02X}&\\t({},{},\\alpha&H{:02X}&)",
+                    ass_alpha(a, from.alpha),
+                    from.time.round(),
+                    to.time.round(),
+                    ass_alpha(a, to.alpha),
+                );
+            }
+            (Some(key), _) => {
+                let _ = write!(tags, "\\alpha&H{:02X}&", ass_alpha(a, key.alpha));
+            }
+            _ if a != 255 => {
+                let _ = write!(tags, "\\alpha&H{:02X}&", ass_alpha(a, 1.0));
+            }
+            _ => {}
+        }
+    } else if a != 255 {
+        let _ = write!(tags, "\\alpha&H{:02X}&", ass_alpha(a, 1.0));
+    }
+
+    // Same size the rows were laid out with
+    let scale = danmaku.scale();
+    if scale != 1.0 {
+        let _ = write!(tags, "\\fs{}", (options.font_size * scale).round());
+    }
+
+    format!(
+        "Dialogue: {layer},{},{},{STYLE_NAME},,0,0,0,,{{{tags}}}{}",
+        timestamp(placed.start),
+        timestamp(placed.end),
+        escape(&danmaku.content),
+    )
+}
+
+fn push_move(tags: &mut String, from: (f32, f32), to: (f32, f32), times: Option<(f32, f32)>) {
+    let _ = write!(
+        tags,
+        "\\move({},{},{},{}",
+        px(from.0),
+        px(from.1),
+        px(to.0),
+        px(to.1)
+    );
+    if let Some((start, end)) = times {
+        let _ = write!(tags, ",{},{}", start.round(), end.round());
+    }
+    tags.push(')');
+}
+
+fn px(value: f32) -> i32 {
+    value.round() as i32
+}
+
+// ASS alpha counts up from opaque
+fn ass_alpha(color_alpha: u8, alpha: f32) -> u8 {
+    255 - (color_alpha as f32 * alpha.clamp(0.0, 1.0)).round() as u8
+}
+
+// H:MM:SS.CC
+fn timestamp(milliseconds: f64) -> String {
+    let centiseconds = (milliseconds.max(0.0) / 10.0).round() as u64;
+
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centiseconds / 360_000,
+        centiseconds / 6000 % 60,
+        centiseconds / 100 % 60,
+        centiseconds % 100
+    )
+}
+
+// Keeps braces and backslashes in the text from being read as override tags
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '{' => escaped.push_str("\\{"),
+            '}' => escaped.push_str("\\}"),
+            '\n' => escaped.push_str("\\N"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("No [Events] section")]
+    MissingEvents,
+    #[error("Invalid timestamp on line {line}: {value}")]
+    InvalidTime { line: usize, value: String },
+}
+
+// Stage size assumed by the ASS spec when the script doesn't set one
+const DEFAULT_PLAY_RES: (f32, f32) = (384.0, 288.0);
+// Used when a script leaves out the events `Format:` line
+const EVENT_FORMAT: &str =
+    "Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Section {
+    ScriptInfo,
+    Styles,
+    Events,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Style {
+    font_size: f32,
+    color: Color,
+    alignment: Option<u8>,
+}
+
+// Column positions taken from a `Format:` line
+#[derive(Debug, Default)]
+struct Columns {
+    names: Vec<String>,
+}
+
+impl Columns {
+    fn parse(format: &str) -> Self {
+        Self {
+            names: format
+                .split(',')
+                .map(|name| name.trim().to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|column| column == name)
+    }
+
+    // The last column (Text) may itself contain commas
+    fn split<'a>(&self, values: &'a str) -> Vec<&'a str> {
+        values.splitn(self.names.len().max(1), ',').collect()
+    }
+}
+
+/// Reads the events of an ASS file made by a danmaku converter. Events that
+/// are neither moving nor pinned with `\pos` are ordinary subtitles and
+/// get skipped.
+pub fn parse_str(ass: &str) -> Result<Vec<Danmaku>, ParseError> {
+    parse_reader(ass.as_bytes())
+}
+
+pub fn parse_reader<R: BufRead>(reader: R) -> Result<Vec<Danmaku>, ParseError> {
+    let mut section = Section::Other;
+    let mut play_res = (None, None);
+    let mut styles: HashMap<String, Style> = HashMap::new();
+    let mut style_columns = Columns::default();
+    let mut event_columns = None;
+    let mut danmaku = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_start_matches('\u{FEFF}').trim();
+
+        if line.starts_with('[') && line.ends_with(']') {
+            section = match line.to_ascii_lowercase().as_str() {
+                "[script info]" => Section::ScriptInfo,
+                "[v4+ styles]" | "[v4 styles]" => Section::Styles,
+                "[events]" => Section::Events,
+                _ => Section::Other,
+            };
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim_start();
+
+        match (section, key.trim()) {
+            (Section::ScriptInfo, "PlayResX") => play_res.0 = value.parse().ok(),
+            (Section::ScriptInfo, "PlayResY") => play_res.1 = value.parse().ok(),
+            (Section::Styles, "Format") => style_columns = Columns::parse(value),
+            (Section::Styles, "Style") => {
+                if let Some((name, style)) = parse_style(&style_columns, value) {
+                    styles.insert(name, style);
+                }
+            }
+            (Section::Events, "Format") => event_columns = Some(Columns::parse(value)),
+            (Section::Events, "Dialogue") => {
+                let columns = event_columns.get_or_insert_with(|| Columns::parse(EVENT_FORMAT));
+                let play_res = (
+                    play_res.0.unwrap_or(DEFAULT_PLAY_RES.0),
+                    play_res.1.unwrap_or(DEFAULT_PLAY_RES.1),
+                );
+                let event = parse_event(columns, value, &styles, play_res, index + 1)?;
+                danmaku.extend(event);
+            }
+            _ => {}
+        }
+    }
+
+    if event_columns.is_none() {
+        return Err(ParseError::MissingEvents);
+    }
+
+    Ok(danmaku)
+}
+
+fn parse_style(columns: &Columns, value: &str) -> Option<(String, Style)> {
+    let fields = columns.split(value);
+    let field = |name: &str| columns.index(name).and_then(|index| fields.get(index));
+
+    let name = field("name")?.trim().to_string();
+    let style = Style {
+        font_size: field("fontsize")?.trim().parse().ok()?,
+        color: field("primarycolour")
+            .and_then(|color| parse_color(color))
+            .unwrap_or_default(),
+        alignment: field("alignment").and_then(|alignment| alignment.trim().parse().ok()),
+    };
+
+    Some((name, style))
+}
+
+fn parse_event(
+    columns: &Columns, value: &str, styles: &HashMap<String, Style>, play_res: (f32, f32),
+    line: usize,
+) -> Result<Option<Danmaku>, ParseError> {
+    let fields = columns.split(value);
+    let field = |name: &str| {
+        columns
+            .index(name)
+            .and_then(|index| fields.get(index))
+            .map(|field| field.trim())
+    };
+
+    let time = |name: &str| {
+        let value = field(name).unwrap_or_default();
+        parse_timestamp(value).ok_or_else(|| ParseError::InvalidTime {
+            line,
+            value: value.to_string(),
+        })
+    };
+    let start = time("start")?;
+    let end = time("end")?;
+
+    let style = field("style")
+        .and_then(|name| styles.get(name.trim_start_matches('*')))
+        .copied();
+    // Text is the last column, only its leading whitespace is insignificant
+    let text = columns
+        .index("text")
+        .and_then(|index| fields.get(index))
+        .map_or("", |text| text.trim_start());
+
+    let (content, mut tags) = parse_text(text);
+    tags.alignment = tags.alignment.or(style.and_then(|style| style.alignment));
+    let Some(mode) = tags.mode(start, end, play_res) else {
+        return Ok(None);
+    };
+
+    let mut color = tags.color.or(style.map(|style| style.color)).unwrap_or_default();
+    if let Some(alpha) = tags.alpha {
+        color.a = 255 - alpha;
+    }
+
+    let size = tags
+        .font_size
+        .zip(style.map(|style| style.font_size))
+        .map(|(size, base)| size / base)
+        .filter(|&scale| is_valid_size(scale) && scale != 1.0);
+
+    Ok(Some(Danmaku {
+        content,
+        start,
+        color,
+        mode,
+        size,
+        ..Default::default()
+    }))
+}
+
+// Override tags that matter for danmaku, later ones win like in renderers
+#[derive(Debug, Default)]
+struct Tags {
+    movement: Option<[f32; 6]>,
+    position: Option<(f32, f32)>,
+    alignment: Option<u8>,
+    color: Option<Color>,
+    alpha: Option<u8>,
+    font_size: Option<f32>,
+}
+
+impl Tags {
+    fn mode(&self, start: f64, end: f64, play_res: (f32, f32)) -> Option<DanmakuMode> {
+        let (width, height) = play_res;
+
+        if let Some([x1, y1, x2, y2, t1, t2]) = self.movement {
+            let timed = t1 != 0.0 || t2 != 0.0;
+            if y1 == y2 && !timed {
+                return Some(if x1 > x2 {
+                    DanmakuMode::Scroll
+                } else {
+                    DanmakuMode::ReverseScroll
+                });
+            }
+
+            let duration = (end - start) as f32;
+            let (t1, t2) = if timed { (t1, t2) } else { (0.0, duration) };
+            return Some(DanmakuMode::Positioned(Box::new(MotionSpec {
+                duration,
+                path: vec![
+                    PathPoint {
+                        time: t1,
+                        x: x1 / width,
+                        y: y1 / height,
+                    },
+                    PathPoint {
+                        time: t2,
+                        x: x2 / width,
+                        y: y2 / height,
+                    },
+                ],
+                alpha: Vec::new(),
+                rotate_z: 0.0,
+                rotate_y: 0.0,
+                font: None,
+            })));
+        }
+
+        let (_, y) = self.position?;
+        // Bottom aligned, or anchored in the lower half like our own exports
+        Some(match self.alignment {
+            Some(1..=3) => DanmakuMode::BottomCenter,
+            _ if y > height / 2.0 => DanmakuMode::BottomCenter,
+            _ => DanmakuMode::TopCenter,
+        })
+    }
+
+    fn apply(&mut self, tag: &str) {
+        let numbers = |args: &str| -> Vec<f32> {
+            args.trim_start_matches('(')
+                .trim_end_matches(')')
+                .split(',')
+                .filter_map(|arg| arg.trim().parse().ok())
+                .collect()
+        };
+
+        if let Some(args) = tag.strip_prefix("move") {
+            match numbers(args)[..] {
+                [x1, y1, x2, y2] => self.movement = Some([x1, y1, x2, y2, 0.0, 0.0]),
+                [x1, y1, x2, y2, t1, t2] => self.movement = Some([x1, y1, x2, y2, t1, t2]),
+                _ => {}
+            }
+        } else if let Some(args) = tag.strip_prefix("pos") {
+            if let [x, y] = numbers(args)[..] {
+                self.position = Some((x, y));
+            }
+        } else if let Some(alignment) = tag.strip_prefix("an") {
+            self.alignment = alignment.trim().parse().ok();
+        } else if let Some(color) = tag
+            .strip_prefix("1c")
+            .or_else(|| tag.strip_prefix('c'))
+            .and_then(parse_color)
+        {
+            self.color = Some(color);
+        } else if let Some(alpha) = tag
+            .strip_prefix("1a")
+            .or_else(|| tag.strip_prefix("alpha"))
+            .and_then(parse_hex)
+        {
+            self.alpha = Some(alpha as u8);
+        } else if tag.starts_with("fs") && !tag.starts_with("fsc") && !tag.starts_with("fsp") {
+            self.font_size = tag[2..].trim().parse().ok();
+        }
+    }
+}
+
+// Splits event text into plain text and its override tags
+fn parse_text(text: &str) -> (String, Tags) {
+    let mut content = String::with_capacity(text.len());
+    let mut tags = Tags::default();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('N' | 'n') => content.push('\n'),
+                Some('h') => content.push('\u{A0}'),
+                Some(c @ ('{' | '}' | '\\')) => content.push(c),
+                Some(c) => {
+                    content.push('\\');
+                    content.push(c);
+                }
+                None => content.push('\\'),
+            },
+            '{' => {
+                let block: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                for tag in split_tags(&block) {
+                    tags.apply(tag);
+                }
+            }
+            c => content.push(c),
+        }
+    }
+
+    (content, tags)
+}
+
+// Tags start with a backslash, except inside parentheses as in `\t(\alpha&HFF&)`
+fn split_tags(block: &str) -> Vec<&str> {
+    let mut tags = Vec::new();
+    let mut depth = 0usize;
+    let mut start = None;
+
+    for (index, c) in block.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            '\\' if depth == 0 => {
+                if let Some(start) = start {
+                    tags.push(block[start..index].trim());
+                }
+                start = Some(index + 1);
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(start) = start {
+        tags.push(block[start..].trim());
+    }
+
+    tags
+}
+
+// &HBBGGRR& or &HAABBGGRR, the alpha is ignored
+fn parse_color(color: &str) -> Option<Color> {
+    let bgr = parse_hex(color)?;
+
+    Some(Color {
+        r: (bgr & 0xFF) as u8,
+        g: ((bgr >> 8) & 0xFF) as u8,
+        b: ((bgr >> 16) & 0xFF) as u8,
+        a: 255,
+    })
+}
+
+fn parse_hex(value: &str) -> Option<u32> {
+    let value = value.trim().trim_start_matches('&');
+    let hex = value
+        .strip_prefix('H')
+        .or_else(|| value.strip_prefix('h'))?
+        .trim_end_matches('&');
+
+    u32::from_str_radix(hex, 16).ok()
+}
+
+// H:MM:SS.CC
+fn parse_timestamp(value: &str) -> Option<f64> {
+    let mut parts = value.split(':');
+    let hours: f64 = parts.next()?.trim().parse().ok()?;
+    let minutes: f64 = parts.next()?.trim().parse().ok()?;
+    let seconds: f64 = parts.next()?.trim().parse().ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(((hours * 60.0 + minutes) * 60.0 + seconds) * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        AlphaKeyframe,
+        FixedWidth,
+    };
+
+    const EXPECTED: &str = include_str!("../../tests/fixtures/ass/basic.ass");
+    const DANMAKU2ASS: &str = include_str!("../../tests/fixtures/ass/danmaku2ass.ass");
+    const DANMAKUFACTORY: &str = include_str!("../../tests/fixtures/ass/danmakufactory.ass");
+
+    fn track() -> Vec<Danmaku> {
+        let danmaku = |content: &str, start: f64, mode: DanmakuMode| Danmaku {
+            content: content.to_string(),
+            start,
+            mode,
+            ..Default::default()
+        };
+
+        vec![
+            danmaku("first", 0.0, DanmakuMode::Scroll),
+            Danmaku {
+                color: Color::from_rgb(0xFF0000),
+                ..danmaku("second", 500.0, DanmakuMode::Scroll)
+            },
+            danmaku("{top}", 1000.0, DanmakuMode::TopCenter),
+            Danmaku {
+                size: Some(1.5),
+                ..danmaku("bottom", 1500.0, DanmakuMode::BottomCenter)
+            },
+            danmaku("reverse", 2000.0, DanmakuMode::ReverseScroll),
+            danmaku(
+                "moving",
+                2500.0,
+                DanmakuMode::Positioned(Box::new(MotionSpec {
+                    duration: 3000.0,
+                    path: vec![
+                        PathPoint {
+                            time: 0.0,
+                            x: 0.1,
+                            y: 0.2,
+                        },
+                        PathPoint {
+                            time: 2000.0,
+                            x: 0.5,
+                            y: 0.5,
+                        },
+                    ],
+                    alpha: vec![
+                        AlphaKeyframe {
+                            time: 0.0,
+                            alpha: 1.0,
+                        },
+                        AlphaKeyframe {
+                            time: 3000.0,
+                            alpha: 0.0,
+                        },
+                    ],
+                    rotate_z: 0.0,
+                    rotate_y: 0.0,
+                    font: None,
+                })),
+            ),
+        ]
+    }
+
+    fn options() -> LayoutOptions {
+        LayoutOptions {
+            width: 1280.0,
+            height: 720.0,
+            font_name: "Noto Sans CJK SC".to_string(),
+            font_size: 30.0,
+            line_height: 40.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_export_matches_fixture() {
+        let ass = to_string(&track(), &options(), &mut FixedWidth(0.5));
+        assert_eq!(ass, EXPECTED);
+
+        let mut written = Vec::new();
+        write(&mut written, &track(), &options(), &mut FixedWidth(0.5)).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), ass);
+    }
+
+    #[test]
+    fn test_export_invalid_size() {
+        let track: Vec<Danmaku> = [Some(f32::NAN), Some(f32::INFINITY), Some(0.0), Some(-1.0)]
+            .into_iter()
+            .map(|size| Danmaku {
+                size,
+                ..Danmaku::at("odd", 0.0)
+            })
+            .collect();
+        let ass = to_string(&track, &options(), &mut FixedWidth(0.5));
+
+        assert_eq!(ass.matches("Dialogue:").count(), 4);
+        assert!(!ass.contains("\\fs"));
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0.0), "0:00:00.00");
+        assert_eq!(timestamp(61_234.0), "0:01:01.23");
+        assert_eq!(timestamp(3_723_450.0), "1:02:03.45");
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(r"a{\b}c"), r"a\{\\b\}c");
+        assert_eq!(escape("line\r\nbreak"), r"line\Nbreak");
+    }
+
+    #[test]
+    fn test_parse_danmaku2ass() {
+        let danmaku = parse_str(DANMAKU2ASS).unwrap();
+        assert_eq!(danmaku.len(), 5);
+
+        assert_eq!(danmaku[0].content, "前方高能");
+        assert_eq!(danmaku[0].start, 1500.0);
+        assert_eq!(danmaku[0].mode, DanmakuMode::Scroll);
+        assert_eq!(danmaku[0].color, Color::default());
+        assert_eq!(danmaku[0].size, None);
+
+        assert_eq!(danmaku[1].mode, DanmakuMode::TopCenter);
+        assert_eq!(danmaku[1].color, Color::from_rgb(0xFF0000));
+
+        assert_eq!(danmaku[2].mode, DanmakuMode::BottomCenter);
+        assert_eq!(danmaku[2].size, Some(0.75));
+
+        assert_eq!(danmaku[3].content, "a, b\nc {x}");
+        assert_eq!(danmaku[3].color, Color::from_rgb(0x00CCFF));
+
+        assert_eq!(danmaku[4].start, 62340.0);
+        assert_eq!(danmaku[4].mode, DanmakuMode::ReverseScroll);
+    }
+
+    #[test]
+    fn test_parse_danmakufactory() {
+        let danmaku = parse_str(DANMAKUFACTORY).unwrap();
+
+        // The plain subtitle and the comment are skipped
+        assert_eq!(
+            danmaku
+                .iter()
+                .map(|danmaku| (danmaku.content.as_str(), danmaku.mode.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("草", DanmakuMode::Scroll),
+                ("yellow top", DanmakuMode::TopCenter),
+                ("bottom", DanmakuMode::BottomCenter),
+            ]
+        );
+        assert_eq!(danmaku[1].color, Color::from_rgb(0xFFFF00));
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let exported = to_string(&track(), &options(), &mut FixedWidth(0.5));
+        let danmaku = parse_str(&exported).unwrap();
+
+        assert_eq!(danmaku.len(), track().len());
+        for (parsed, original) in danmaku.iter().zip(track()) {
+            assert_eq!(parsed.content, original.content);
+            assert_eq!(parsed.start, original.start);
+            assert_eq!(parsed.color, original.color);
+            assert_eq!(parsed.size, original.size);
+            if !matches!(original.mode, DanmakuMode::Positioned(_)) {
+                assert_eq!(parsed.mode, original.mode);
+            }
+        }
+
+        let DanmakuMode::Positioned(spec) = &danmaku[5].mode else {
+            panic!("expected a positioned danmaku, got {:?}", danmaku[5].mode);
+        };
+        assert_eq!(spec.duration, 3000.0);
+        assert_eq!(spec.position_at(0.0), (0.1, 0.2));
+        assert_eq!(spec.position_at(2000.0), (0.5, 0.5));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            parse_str("[Script Info]\nPlayResX: 1280\n"),
+            Err(ParseError::MissingEvents)
+        ));
+
+        let ass = "[Events]\nDialogue: 0,soon,0:00:01.00,Default,,0,0,0,,{\\pos(0,0)}a\n";
+        assert!(matches!(
+            parse_str(ass),
+            Err(ParseError::InvalidTime { line: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,927 @@
+//! Bilibili legacy XML comment format (`<i><d p="...">text</d></i>`).
+
+use std::{
+    fmt,
+    io::{
+        self,
+        BufRead,
+        Read,
+    },
+    ops::Range,
+};
+
+use quick_xml::{
+    Reader,
+    events::{
+        Event,
+        attributes::AttrError,
+    },
+};
+use thiserror::Error;
+
+use super::{
+    encoding::Utf8Reader,
+    xml::{
+        read_content,
+        read_repaired_content,
+    },
+};
+use crate::{
+    AlphaKeyframe,
+    Color,
+    Danmaku,
+    DanmakuMeta,
+    DanmakuMode,
+    MotionSpec,
+    PathPoint,
+    danmaku::is_valid_size,
+};
+
+/// Location of an element in the source document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// 1-based line number.
+    pub line: usize,
+    /// Byte offset from the start of the document.
+    pub offset: u64,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} (byte {})", self.line, self.offset)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("XML parsing error at {position}: {source}")]
+    Xml {
+        position: Position,
+        #[source]
+        source: quick_xml::Error,
+    },
+    #[error("Invalid danmaku at {position}: {source}")]
+    Entry {
+        position: Position,
+        #[source]
+        source: EntryError,
+    },
+}
+
+/// Why a single `<d>` element could not be turned into a [`Danmaku`].
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum EntryError {
+    #[error("Attribute parsing error: {0}")]
+    Attribute(#[from] AttrError),
+    #[error("Missing 'p' attribute")]
+    MissingP,
+    #[error("Invalid attribute format for 'p': {0}")]
+    InvalidPFormat(String),
+    #[error("Failed to parse float value: {0}")]
+    ParseFloat(#[from] std::num::ParseFloatError),
+    #[error("Failed to parse integer value: {0}")]
+    ParseInt(#[from] std::num::ParseIntError),
+    #[error("Invalid mode 7 payload: {0}")]
+    InvalidMotion(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WarningKind {
+    /// The element was dropped, only produced in lenient mode.
+    Skipped(EntryError),
+    /// The mode is not supported and the danmaku was shown as [`DanmakuMode::Scroll`].
+    UnknownMode(u8),
+    /// Broken escapes or characters XML doesn't allow were dropped from the
+    /// text, or undecodable bytes were replaced with U+FFFD. Only produced in
+    /// repair mode.
+    Repaired,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub position: Position,
+    pub kind: WarningKind,
+}
+
+#[derive(Debug, Default)]
+pub struct Parsed {
+    pub danmaku: Vec<Danmaku>,
+    pub warnings: Vec<Warning>,
+}
+
+impl Parsed {
+    /// Number of entries that were kept after repairing their text.
+    pub fn repaired(&self) -> usize {
+        self.warnings
+            .iter()
+            .filter(|warning| warning.kind == WarningKind::Repaired)
+            .count()
+    }
+}
+
+/// Configurable parser, the free functions below use the strict defaults.
+#[derive(Debug, Clone, Default)]
+pub struct Parser {
+    lenient: bool,
+    repair: bool,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skip broken `<d>` elements and report them as warnings instead of failing.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Read legacy encoded dumps and clean up text that would fail the whole
+    /// file. The encoding comes from a BOM or the XML declaration, with charset
+    /// detection as a fallback. Positions then refer to the UTF-8 text.
+    pub fn repair(mut self, repair: bool) -> Self {
+        self.repair = repair;
+        self
+    }
+
+    pub fn parse_str(&self, xml: &str) -> Result<Parsed, ParseError> {
+        self.parse_reader(xml.as_bytes())
+    }
+
+    pub fn parse_slice(&self, xml: &[u8]) -> Result<Parsed, ParseError> {
+        self.parse_reader(xml)
+    }
+
+    pub fn parse_reader<R: BufRead>(&self, reader: R) -> Result<Parsed, ParseError> {
+        let mut parsed = Parsed::default();
+
+        for batch in self.batches(reader, usize::MAX) {
+            let batch = batch?;
+            parsed.danmaku.extend(batch.danmaku);
+            parsed.warnings.extend(batch.warnings);
+        }
+
+        Ok(parsed)
+    }
+
+    /// Parses incrementally, yielding up to `batch_size` danmaku at a time so
+    /// huge files don't have to be read completely before playback.
+    pub fn batches<R: BufRead>(&self, reader: R, batch_size: usize) -> Batches<R> {
+        Batches {
+            reader: Reader::from_reader(LineCounter::new(Input::new(reader, self.repair))),
+            buf: Vec::new(),
+            lenient: self.lenient,
+            repair: self.repair,
+            batch_size: batch_size.max(1),
+            done: false,
+        }
+    }
+}
+
+/// Iterator returned by [`Parser::batches`]. It stops after the first error.
+pub struct Batches<R> {
+    reader: Reader<LineCounter<Input<R>>>,
+    buf: Vec<u8>,
+    lenient: bool,
+    repair: bool,
+    batch_size: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Batches<R> {
+    // Reads up to the next `<d>` element, `false` at the end of the document
+    fn read_entry(&mut self, batch: &mut Parsed) -> Result<bool, ParseError> {
+        let reader = &mut self.reader;
+        let buf = &mut self.buf;
+
+        loop {
+            let position = Position {
+                line: reader.get_ref().line(),
+                offset: reader.buffer_position(),
+            };
+
+            let (p_value, content, repaired) = match reader.read_event_into(buf) {
+                Ok(Event::Start(e)) if e.name().as_ref() == b"d" => {
+                    let p_value = p_attribute(&e, reader);
+                    let name = e.name().as_ref().to_vec();
+                    let content = if self.repair {
+                        read_repaired_content(reader, &name, buf).map(|(content, repaired)| {
+                            let range = position.offset..reader.buffer_position();
+                            let replaced = reader.get_mut().inner.replaced_within(range);
+                            (content, repaired || replaced)
+                        })
+                    } else {
+                        read_content(reader, &name, buf).map(|content| (content, false))
+                    };
+                    let (content, repaired) =
+                        content.map_err(|source| xml_error(reader, source))?;
+                    // Dumps are often pretty-printed, the text never starts or
+                    // ends with whitespace on purpose
+                    (p_value, content.trim().to_string(), repaired)
+                }
+                Ok(Event::Empty(e)) if e.name().as_ref() == b"d" => {
+                    (p_attribute(&e, reader), String::new(), false)
+                }
+                Ok(Event::Eof) => return Ok(false),
+                Err(source) => return Err(xml_error(reader, source)),
+                _ => {
+                    buf.clear();
+                    continue;
+                }
+            };
+
+            buf.clear();
+
+            match p_value.and_then(|p| parse_entry(&p, content)) {
+                Ok((danmaku, unknown_mode)) => {
+                    if repaired {
+                        batch.warnings.push(Warning {
+                            position,
+                            kind: WarningKind::Repaired,
+                        });
+                    }
+                    if let Some(mode) = unknown_mode {
+                        batch.warnings.push(Warning {
+                            position,
+                            kind: WarningKind::UnknownMode(mode),
+                        });
+                    }
+                    batch.danmaku.push(danmaku);
+                }
+                Err(source) if self.lenient => batch.warnings.push(Warning {
+                    position,
+                    kind: WarningKind::Skipped(source),
+                }),
+                Err(source) => return Err(ParseError::Entry { position, source }),
+            }
+
+            return Ok(true);
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Batches<R> {
+    type Item = Result<Parsed, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut batch = Parsed::default();
+        while batch.danmaku.len() < self.batch_size {
+            match self.read_entry(&mut batch) {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.done = true;
+                    break;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        if self.done && batch.danmaku.is_empty() && batch.warnings.is_empty() {
+            return None;
+        }
+
+        Some(Ok(batch))
+    }
+}
+
+pub fn parse_str(xml: &str) -> Result<Vec<Danmaku>, ParseError> {
+    Parser::new().parse_str(xml).map(|parsed| parsed.danmaku)
+}
+
+pub fn parse_slice(xml: &[u8]) -> Result<Vec<Danmaku>, ParseError> {
+    Parser::new().parse_slice(xml).map(|parsed| parsed.danmaku)
+}
+
+pub fn parse_reader<R: BufRead>(reader: R) -> Result<Vec<Danmaku>, ParseError> {
+    Parser::new().parse_reader(reader).map(|parsed| parsed.danmaku)
+}
+
+fn xml_error<R>(reader: &Reader<LineCounter<R>>, source: quick_xml::Error) -> ParseError {
+    ParseError::Xml {
+        position: Position {
+            line: reader.get_ref().line(),
+            offset: reader.error_position(),
+        },
+        source,
+    }
+}
+
+fn p_attribute<R>(
+    e: &quick_xml::events::BytesStart, reader: &Reader<R>,
+) -> Result<String, EntryError> {
+    for attr in e.attributes() {
+        let attr = attr?;
+        if attr.key.as_ref() == b"p" {
+            return attr
+                .decode_and_unescape_value(reader.decoder())
+                .map(|value| value.into_owned())
+                .map_err(|e| EntryError::InvalidPFormat(e.to_string()));
+        }
+    }
+
+    Err(EntryError::MissingP)
+}
+
+// Every other size is relative to this one
+pub(super) const STANDARD_FONT_SIZE: f32 = 25.0;
+
+// p = "time,mode,size,color[,timestamp,pool,sender,id[,weight]]"
+// Some third-party dumps only keep the first four fields.
+fn parse_entry(p_value: &str, content: String) -> Result<(Danmaku, Option<u8>), EntryError> {
+    let parts: Vec<&str> = p_value.split(',').collect();
+    if parts.len() < 4 {
+        return Err(EntryError::InvalidPFormat(p_value.to_string()));
+    }
+
+    let start: f64 = parts[0].trim().parse()?;
+    let mode_val: u8 = parts[1].trim().parse()?;
+    let font_size: f32 = parts[2].trim().parse()?;
+    let color_val: u32 = parts[3].trim().parse()?;
+
+    let field = |index: usize| parts.get(index).map(|part| part.trim());
+    let meta = DanmakuMeta {
+        send_time: field(4).map(str::parse).transpose()?,
+        pool: field(5).map(str::parse).transpose()?,
+        sender: field(6).map(str::to_string),
+        id: field(7).map(str::parse).transpose()?,
+        weight: field(8).map(str::parse).transpose()?,
+        ..Default::default()
+    };
+
+    build_entry(start * 1000.0, mode_val, font_size, color_val, content, meta)
+}
+
+// Shared with the protobuf segments, which carry the same fields.
+// Also returns the mode when it is unknown and was shown as scrolling.
+pub(super) fn build_entry(
+    start: f64, mode_val: u8, font_size: f32, color_val: u32, content: String, meta: DanmakuMeta,
+) -> Result<(Danmaku, Option<u8>), EntryError> {
+    let (mode, content, unknown_mode) = match (mode_val, mode_from_code(mode_val)) {
+        (7, _) => {
+            let (spec, text) = parse_motion(&content)?;
+            (DanmakuMode::Positioned(Box::new(spec)), text, None)
+        }
+        (_, Some(mode)) => (mode, content, None),
+        (_, None) => (DanmakuMode::Scroll, content, Some(mode_val)),
+    };
+
+    let danmaku = Danmaku {
+        content,
+        start,
+        color: Color::from_rgb(color_val),
+        mode,
+        size: (is_valid_size(font_size) && font_size != STANDARD_FONT_SIZE)
+            .then_some(font_size / STANDARD_FONT_SIZE),
+        meta,
+        ..Default::default()
+    };
+
+    Ok((danmaku, unknown_mode))
+}
+
+/// Maps a Bilibili mode code onto a [`DanmakuMode`].
+pub fn mode_from_code(code: u8) -> Option<DanmakuMode> {
+    match code {
+        1..=3 => Some(DanmakuMode::Scroll),
+        4 => Some(DanmakuMode::BottomCenter),
+        5 => Some(DanmakuMode::TopCenter),
+        6 => Some(DanmakuMode::ReverseScroll),
+        _ => None,
+    }
+}
+
+// Absolute mode 7 coordinates are given in the old Flash player's stage
+const STAGE_WIDTH: f32 = 512.0;
+const STAGE_HEIGHT: f32 = 384.0;
+// Used when the payload leaves the lifetime out, in seconds
+const DEFAULT_MOTION_DURATION: f32 = 4.5;
+
+// Mode 7 content is a JSON array:
+// [x1, y1, "alpha1-alpha2", duration, text, rotate_z, rotate_y,
+//  x2, y2, move_duration, move_delay, stroke, font, linear, path]
+// Numbers are sometimes sent as strings, everything after the text is optional.
+fn parse_motion(content: &str) -> Result<(MotionSpec, String), EntryError> {
+    let invalid = || EntryError::InvalidMotion(content.to_string());
+
+    let value: serde_json::Value = serde_json::from_str(content).map_err(|_| invalid())?;
+    let fields = value.as_array().ok_or_else(invalid)?;
+    let number = |index: usize| fields.get(index).and_then(json_number);
+    let x = |index: usize| fields.get(index).and_then(|v| stage_coordinate(v, STAGE_WIDTH));
+    let y = |index: usize| fields.get(index).and_then(|v| stage_coordinate(v, STAGE_HEIGHT));
+
+    let (x1, y1) = x(0).zip(y(1)).ok_or_else(invalid)?;
+    let text = fields.get(4).and_then(|v| v.as_str()).ok_or_else(invalid)?;
+
+    let duration = number(3).unwrap_or(DEFAULT_MOTION_DURATION) * 1000.0;
+
+    let (alpha_from, alpha_to) = match fields.get(2) {
+        Some(serde_json::Value::String(alpha)) => {
+            let mut alphas = alpha.split('-').map(|a| a.trim().parse::<f32>());
+            let from = alphas.next().and_then(Result::ok).unwrap_or(1.0);
+            let to = alphas.next().and_then(Result::ok).unwrap_or(from);
+            (from, to)
+        }
+        Some(alpha) => {
+            let alpha = json_number(alpha).unwrap_or(1.0);
+            (alpha, alpha)
+        }
+        None => (1.0, 1.0),
+    };
+
+    let move_duration = number(9).unwrap_or(0.0);
+    let move_delay = number(10).unwrap_or(0.0);
+
+    let points = match fields.get(14).and_then(|v| v.as_str()) {
+        Some(path) => parse_path(path).ok_or_else(invalid)?,
+        None => vec![(x1, y1), (x(7).unwrap_or(x1), y(8).unwrap_or(y1))],
+    };
+
+    let step = move_duration / (points.len().max(2) - 1) as f32;
+    let path = points
+        .into_iter()
+        .enumerate()
+        .map(|(i, (x, y))| PathPoint {
+            time: move_delay + step * i as f32,
+            x,
+            y,
+        })
+        .collect();
+
+    let spec = MotionSpec {
+        duration,
+        path,
+        alpha: vec![
+            AlphaKeyframe {
+                time: 0.0,
+                alpha: alpha_from,
+            },
+            AlphaKeyframe {
+                time: duration,
+                alpha: alpha_to,
+            },
+        ],
+        rotate_z: number(5).unwrap_or(0.0),
+        rotate_y: number(6).unwrap_or(0.0),
+        font: fields
+            .get(12)
+            .and_then(|v| v.as_str())
+            .filter(|font| !font.is_empty())
+            .map(str::to_string),
+    };
+
+    Ok((spec, text.replace("/n", "\n")))
+}
+
+fn json_number(value: &serde_json::Value) -> Option<f32> {
+    match value {
+        serde_json::Value::Number(n) => n.as_f64().map(|n| n as f32),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+// Decimals up to 1 are fractions of the stage, anything else is in stage pixels
+fn stage_coordinate(value: &serde_json::Value, stage: f32) -> Option<f32> {
+    let raw = match value {
+        serde_json::Value::String(s) => s.trim().to_string(),
+        other => other.to_string(),
+    };
+    let coordinate = json_number(value)?;
+
+    if raw.contains('.') && coordinate <= 1.0 {
+        Some(coordinate)
+    } else {
+        Some(coordinate / stage)
+    }
+}
+
+// "M x,y L x,y L x,y ..." in stage pixels
+fn parse_path(path: &str) -> Option<Vec<(f32, f32)>> {
+    path.split(['M', 'L'])
+        .map(str::trim)
+        .filter(|point| !point.is_empty())
+        .map(|point| {
+            let (x, y) = point.split_once(',')?;
+            let x: f32 = x.trim().parse().ok()?;
+            let y: f32 = y.trim().parse().ok()?;
+            Some((x / STAGE_WIDTH, y / STAGE_HEIGHT))
+        })
+        .collect::<Option<Vec<_>>>()
+        .filter(|points| !points.is_empty())
+}
+
+// Only goes through the transcoder when repairing
+enum Input<R> {
+    Utf8(R),
+    Transcoded(Utf8Reader<R>),
+}
+
+impl<R: BufRead> Input<R> {
+    fn new(inner: R, repair: bool) -> Self {
+        if repair {
+            Input::Transcoded(Utf8Reader::new(inner))
+        } else {
+            Input::Utf8(inner)
+        }
+    }
+
+    fn replaced_within(&mut self, range: Range<u64>) -> bool {
+        match self {
+            Input::Utf8(_) => false,
+            Input::Transcoded(inner) => inner.replaced_within(range),
+        }
+    }
+}
+
+impl<R: BufRead> Read for Input<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::Utf8(inner) => inner.read(buf),
+            Input::Transcoded(inner) => inner.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for Input<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Input::Utf8(inner) => inner.fill_buf(),
+            Input::Transcoded(inner) => inner.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match self {
+            Input::Utf8(inner) => inner.consume(amt),
+            Input::Transcoded(inner) => inner.consume(amt),
+        }
+    }
+}
+
+// Counts the lines consumed by quick-xml so errors can point at a line
+// even when reading from a stream.
+struct LineCounter<R> {
+    inner: R,
+    line: usize,
+}
+
+impl<R> LineCounter<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, line: 1 }
+    }
+
+    fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl<R: Read> Read for LineCounter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.line += count_lines(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for LineCounter<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // The data is already buffered, so this never hits the underlying reader.
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.line += count_lines(&buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+fn count_lines(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&b| b == b'\n').count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASIC: &str = include_str!("../../tests/fixtures/bilibili/basic.xml");
+    const SHORT_P: &str = include_str!("../../tests/fixtures/bilibili/short_p.xml");
+    const UNKNOWN_MODE: &str = include_str!("../../tests/fixtures/bilibili/unknown_mode.xml");
+    const BROKEN_ENTRIES: &str =
+        include_str!("../../tests/fixtures/bilibili/broken_entries.xml");
+    const FONT_SIZE: &str = include_str!("../../tests/fixtures/bilibili/font_size.xml");
+    const POSITIONED: &str = include_str!("../../tests/fixtures/bilibili/positioned.xml");
+    const MALFORMED: &str = include_str!("../../tests/fixtures/bilibili/malformed.xml");
+    const WHITESPACE: &str = include_str!("../../tests/fixtures/bilibili/whitespace.xml");
+    const GBK: &[u8] = include_bytes!("../../tests/fixtures/bilibili/gbk.xml");
+    const INVALID_CHARS: &[u8] = include_bytes!("../../tests/fixtures/bilibili/invalid_chars.xml");
+
+    #[test]
+    fn test_parse_basic() {
+        let danmaku = parse_str(BASIC).unwrap();
+        assert_eq!(danmaku.len(), 5);
+
+        assert_eq!(danmaku[0].content, "这个森林里的东西可能都没你们一半恐怖");
+        assert_eq!(danmaku[0].start, 530363.0);
+        assert_eq!(danmaku[0].mode, DanmakuMode::TopCenter);
+        assert_eq!(
+            danmaku[0].color,
+            Color {
+                r: 0x00,
+                g: 0x98,
+                b: 0x43,
+                a: 255
+            }
+        );
+
+        assert_eq!(danmaku[2].mode, DanmakuMode::Scroll);
+        assert_eq!(danmaku[3].mode, DanmakuMode::BottomCenter);
+        assert_eq!(danmaku[3].content, "底部 & 红色");
+        assert_eq!(danmaku[3].color, Color::from_rgb(0xFF0000));
+        assert_eq!(danmaku[3].size, None);
+        assert_eq!(danmaku[4].mode, DanmakuMode::ReverseScroll);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let danmaku = parse_str(BASIC).unwrap();
+
+        assert_eq!(
+            danmaku[0].meta,
+            DanmakuMeta {
+                id: Some(1154081814147723520),
+                sender: Some("7a41972".to_string()),
+                send_time: Some(1664668473),
+                pool: Some(0),
+                weight: Some(10),
+                ..Default::default()
+            }
+        );
+
+        let parsed = Parser::new().lenient(true).parse_str(SHORT_P).unwrap();
+        assert_eq!(parsed.danmaku[0].meta, DanmakuMeta::default());
+        assert_eq!(parsed.danmaku[1].meta.send_time, Some(1664644204));
+        assert_eq!(parsed.danmaku[1].meta.sender, None);
+        assert_eq!(parsed.danmaku[2].meta.id, Some(1153878231204752128));
+        assert_eq!(parsed.danmaku[2].meta.weight, None);
+    }
+
+    #[test]
+    fn test_font_size() {
+        let danmaku = parse_str(FONT_SIZE).unwrap();
+
+        let sizes: Vec<_> = danmaku.iter().map(|d| d.size).collect();
+        assert_eq!(sizes, [Some(0.48), Some(0.72), None, Some(1.44), Some(1.8)]);
+
+        let spans: Vec<_> = danmaku.iter().map(|d| d.row_span()).collect();
+        assert_eq!(spans, [1, 1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn test_invalid_font_size() {
+        let xml = r#"<i>
+            <d p="1.0,1,0,16777215">zero</d>
+            <d p="2.0,1,-5,16777215">negative</d>
+            <d p="3.0,1,NaN,16777215">nan</d>
+            <d p="4.0,1,inf,16777215">infinite</d>
+        </i>"#;
+        let danmaku = parse_str(xml).unwrap();
+
+        assert_eq!(danmaku.len(), 4);
+        assert!(danmaku.iter().all(|d| d.size.is_none() && d.scale() == 1.0));
+    }
+
+    #[test]
+    fn test_trim_whitespace() {
+        let danmaku = parse_str(WHITESPACE).unwrap();
+
+        let content: Vec<_> = danmaku.iter().map(|d| d.content.as_str()).collect();
+        assert_eq!(
+            content,
+            ["padded", "on its own line", "inner  spaces & tabs", ""]
+        );
+    }
+
+    #[test]
+    fn test_parse_inputs_agree() {
+        let from_str = parse_str(BASIC).unwrap();
+        let from_slice = parse_slice(BASIC.as_bytes()).unwrap();
+        let from_reader = parse_reader(io::BufReader::with_capacity(16, BASIC.as_bytes())).unwrap();
+
+        assert_eq!(from_str, from_slice);
+        assert_eq!(from_str, from_reader);
+    }
+
+    #[test]
+    fn test_batches() {
+        let sizes: Vec<usize> = Parser::new()
+            .batches(BASIC.as_bytes(), 2)
+            .map(|batch| batch.unwrap().danmaku.len())
+            .collect();
+        assert_eq!(sizes, [2, 2, 1]);
+
+        let batched: Vec<Danmaku> = Parser::new()
+            .batches(BASIC.as_bytes(), 2)
+            .flat_map(|batch| batch.unwrap().danmaku)
+            .collect();
+        assert_eq!(batched, parse_str(BASIC).unwrap());
+    }
+
+    #[test]
+    fn test_batches_stop_at_error() {
+        let mut batches = Parser::new().batches(SHORT_P.as_bytes(), 1);
+        assert!(batches.any(|batch| batch.is_err()));
+        assert!(batches.next().is_none());
+    }
+
+    #[test]
+    fn test_short_p_strict() {
+        let err = parse_str(SHORT_P).unwrap_err();
+        let ParseError::Entry { position, source } = err else {
+            panic!("expected an entry error, got {err:?}");
+        };
+        assert_eq!(position.line, 5);
+        assert_eq!(source, EntryError::InvalidPFormat("3.5,1".to_string()));
+    }
+
+    #[test]
+    fn test_short_p_lenient() {
+        let parsed = Parser::new().lenient(true).parse_str(SHORT_P).unwrap();
+
+        let contents: Vec<_> = parsed.danmaku.iter().map(|d| d.content.as_str()).collect();
+        assert_eq!(contents, ["four fields", "five fields", "eight fields"]);
+        assert_eq!(parsed.danmaku[1].mode, DanmakuMode::TopCenter);
+        assert_eq!(parsed.danmaku[1].color, Color::from_rgb(0x00FF00));
+
+        assert_eq!(parsed.warnings.len(), 1);
+        assert_eq!(parsed.warnings[0].position.line, 5);
+    }
+
+    #[test]
+    fn test_unknown_mode_falls_back_to_scroll() {
+        let parsed = Parser::new().parse_str(UNKNOWN_MODE).unwrap();
+
+        assert_eq!(parsed.danmaku.len(), 3);
+        assert!(
+            parsed
+                .danmaku
+                .iter()
+                .all(|d| d.mode == DanmakuMode::Scroll)
+        );
+
+        let kinds: Vec<_> = parsed.warnings.iter().map(|w| &w.kind).collect();
+        assert_eq!(
+            kinds,
+            [&WarningKind::UnknownMode(8), &WarningKind::UnknownMode(9)]
+        );
+        assert_eq!(parsed.warnings[0].position.line, 4);
+    }
+
+    #[test]
+    fn test_broken_entries_lenient() {
+        let parsed = Parser::new()
+            .lenient(true)
+            .parse_str(BROKEN_ENTRIES)
+            .unwrap();
+
+        assert_eq!(parsed.danmaku.len(), 2);
+        assert_eq!(parsed.danmaku[0].content, "good");
+        assert_eq!(parsed.danmaku[1].content, "");
+
+        let lines: Vec<_> = parsed.warnings.iter().map(|w| w.position.line).collect();
+        assert_eq!(lines, [4, 5, 6]);
+        assert_eq!(
+            parsed.warnings[1].kind,
+            WarningKind::Skipped(EntryError::MissingP)
+        );
+    }
+
+    #[test]
+    fn test_broken_entries_strict() {
+        let err = parse_str(BROKEN_ENTRIES).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::Entry {
+                position: Position { line: 4, .. },
+                source: EntryError::ParseFloat(_),
+            }
+        ));
+    }
+
+    fn motion(danmaku: &Danmaku) -> &MotionSpec {
+        match &danmaku.mode {
+            DanmakuMode::Positioned(spec) => spec,
+            mode => panic!("expected a positioned danmaku, got {mode:?}"),
+        }
+    }
+
+    #[test]
+    fn test_positioned() {
+        let parsed = Parser::new().lenient(true).parse_str(POSITIONED).unwrap();
+        assert_eq!(parsed.danmaku.len(), 3);
+
+        let relative = &parsed.danmaku[0];
+        assert_eq!(relative.content, "relative\nsubtitle");
+        assert_eq!(
+            *motion(relative),
+            MotionSpec {
+                duration: 4000.0,
+                path: vec![
+                    PathPoint {
+                        time: 500.0,
+                        x: 0.1,
+                        y: 0.2,
+                    },
+                    PathPoint {
+                        time: 2500.0,
+                        x: 0.5,
+                        y: 0.6,
+                    },
+                ],
+                alpha: vec![
+                    AlphaKeyframe {
+                        time: 0.0,
+                        alpha: 1.0,
+                    },
+                    AlphaKeyframe {
+                        time: 4000.0,
+                        alpha: 0.0,
+                    },
+                ],
+                rotate_z: 0.0,
+                rotate_y: 0.0,
+                font: Some("SimHei".to_string()),
+            }
+        );
+
+        let pixels = motion(&parsed.danmaku[1]);
+        assert_eq!(pixels.duration, 2500.0);
+        assert_eq!(pixels.position_at(0.0), (0.5, 0.5));
+        assert_eq!(pixels.position_at(1000.0), (0.5, 0.5));
+        assert_eq!(pixels.alpha_at(1000.0), 0.8);
+        assert_eq!(pixels.font, None);
+
+        let path = motion(&parsed.danmaku[2]);
+        let points: Vec<_> = path.path.iter().map(|p| (p.time, p.x, p.y)).collect();
+        assert_eq!(points, [(0.0, 0.0, 1.0), (500.0, 0.5, 0.5), (1000.0, 1.0, 0.0)]);
+
+        assert_eq!(parsed.warnings.len(), 1);
+        assert!(matches!(
+            parsed.warnings[0].kind,
+            WarningKind::Skipped(EntryError::InvalidMotion(_))
+        ));
+    }
+
+    #[test]
+    fn test_malformed_xml() {
+        let err = Parser::new()
+            .lenient(true)
+            .parse_str(MALFORMED)
+            .unwrap_err();
+        let ParseError::Xml { position, .. } = err else {
+            panic!("expected an XML error, got {err:?}");
+        };
+        assert_eq!(position.line, 4);
+    }
+
+    #[test]
+    fn test_repair_encoding() {
+        assert!(parse_slice(GBK).is_err());
+
+        let parsed = Parser::new().repair(true).parse_slice(GBK).unwrap();
+        let content: Vec<_> = parsed.danmaku.iter().map(|d| d.content.as_str()).collect();
+        assert_eq!(content, ["前排围观", "字幕君辛苦了"]);
+        assert_eq!(parsed.repaired(), 0);
+    }
+
+    #[test]
+    fn test_repair_invalid_chars() {
+        assert!(parse_slice(INVALID_CHARS).is_err());
+
+        let parsed = Parser::new().repair(true).parse_slice(INVALID_CHARS).unwrap();
+        let content: Vec<_> = parsed.danmaku.iter().map(|d| d.content.as_str()).collect();
+        assert_eq!(
+            content,
+            [
+                "clean",
+                "tab\there",
+                "bell and backspace",
+                "null  ref & &unknown;",
+                "无效\u{FFFD} byte",
+            ]
+        );
+
+        let lines: Vec<_> = parsed.warnings.iter().map(|w| w.position.line).collect();
+        assert_eq!(lines, [5, 6, 7]);
+        assert_eq!(parsed.repaired(), 3);
+    }
+}
@@ -0,0 +1,209 @@
+//! Bilibili protobuf comment segments (`DmSegMobileReply`), which the current
+//! API serves in 6 minute chunks. Only already downloaded data is decoded.
+
+use std::io::{
+    self,
+    Read,
+};
+
+use prost::Message;
+use thiserror::Error;
+
+use super::bilibili::{
+    EntryError,
+    STANDARD_FONT_SIZE,
+    build_entry,
+};
+use crate::{
+    Danmaku,
+    DanmakuMeta,
+    DanmakuQueue,
+};
+
+#[derive(Clone, PartialEq, Message)]
+pub struct DmSegMobileReply {
+    #[prost(message, repeated, tag = "1")]
+    pub elems: Vec<DanmakuElem>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct DanmakuElem {
+    #[prost(int64, tag = "1")]
+    pub id: i64,
+    // Milliseconds from the start of the video
+    #[prost(int32, tag = "2")]
+    pub progress: i32,
+    #[prost(int32, tag = "3")]
+    pub mode: i32,
+    #[prost(int32, tag = "4")]
+    pub fontsize: i32,
+    #[prost(uint32, tag = "5")]
+    pub color: u32,
+    #[prost(string, tag = "6")]
+    pub mid_hash: String,
+    #[prost(string, tag = "7")]
+    pub content: String,
+    #[prost(int64, tag = "8")]
+    pub ctime: i64,
+    #[prost(int32, tag = "9")]
+    pub weight: i32,
+    #[prost(string, tag = "10")]
+    pub action: String,
+    #[prost(int32, tag = "11")]
+    pub pool: i32,
+    #[prost(string, tag = "12")]
+    pub id_str: String,
+    #[prost(int32, tag = "13")]
+    pub attr: i32,
+}
+
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Protobuf decoding error: {0}")]
+    Decode(#[from] prost::DecodeError),
+    #[error("Invalid danmaku {id}: {source}")]
+    Entry {
+        id: i64,
+        #[source]
+        source: EntryError,
+    },
+}
+
+pub fn parse_slice(segment: &[u8]) -> Result<Vec<Danmaku>, ParseError> {
+    DmSegMobileReply::decode(segment)?
+        .elems
+        .into_iter()
+        .map(|elem| {
+            let id = elem.id;
+            convert(elem).map_err(|source| ParseError::Entry { id, source })
+        })
+        .collect()
+}
+
+pub fn parse_reader<R: Read>(mut reader: R) -> Result<Vec<Danmaku>, ParseError> {
+    let mut segment = Vec::new();
+    reader.read_to_end(&mut segment)?;
+    parse_slice(&segment)
+}
+
+/// Decodes every segment before touching the queue, so a broken segment
+/// leaves it unchanged. Returns the number of danmaku added.
+pub fn extend_queue<I, S>(queue: &mut DanmakuQueue, segments: I) -> Result<usize, ParseError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<[u8]>,
+{
+    let segments = segments
+        .into_iter()
+        .map(|segment| parse_slice(segment.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut added = 0;
+    for danmaku in segments {
+        added += danmaku.len();
+        queue.extend(danmaku);
+    }
+
+    Ok(added)
+}
+
+fn convert(elem: DanmakuElem) -> Result<Danmaku, EntryError> {
+    let meta = DanmakuMeta {
+        id: u64::try_from(elem.id).ok(),
+        sender: (!elem.mid_hash.is_empty()).then_some(elem.mid_hash),
+        send_time: (elem.ctime > 0).then_some(elem.ctime),
+        pool: u32::try_from(elem.pool).ok(),
+        weight: u8::try_from(elem.weight).ok(),
+        ..Default::default()
+    };
+
+    // Zero means the field was left out
+    let font_size = match elem.fontsize {
+        0 => STANDARD_FONT_SIZE,
+        size => size as f32,
+    };
+
+    // Out of range modes end up as unknown and scroll
+    let mode = u8::try_from(elem.mode).unwrap_or(u8::MAX);
+
+    build_entry(elem.progress as f64, mode, font_size, elem.color, elem.content, meta)
+        .map(|(danmaku, _)| danmaku)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Color,
+        DanmakuMode,
+    };
+
+    const SEGMENT_1: &[u8] =
+        include_bytes!("../../tests/fixtures/bilibili_protobuf/segment_1.bin");
+    const SEGMENT_2: &[u8] =
+        include_bytes!("../../tests/fixtures/bilibili_protobuf/segment_2.bin");
+
+    #[test]
+    fn test_parse_segment() {
+        let danmaku = parse_slice(SEGMENT_1).unwrap();
+        assert_eq!(danmaku.len(), 3);
+
+        assert_eq!(danmaku[0].content, "前排");
+        assert_eq!(danmaku[0].start, 1500.0);
+        assert_eq!(danmaku[0].mode, DanmakuMode::Scroll);
+        assert_eq!(danmaku[0].size, None);
+        assert_eq!(danmaku[0].color, Color::from_rgb(0xFFFFFF));
+        assert_eq!(
+            danmaku[0].meta,
+            DanmakuMeta {
+                id: Some(1001),
+                sender: Some("3a8c9f2b".to_string()),
+                send_time: Some(1700000000),
+                pool: Some(0),
+                weight: Some(10),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(danmaku[1].mode, DanmakuMode::TopCenter);
+        assert_eq!(danmaku[1].size, Some(36.0 / 25.0));
+        assert_eq!(danmaku[1].color, Color::from_rgb(0xFF0000));
+        assert_eq!(danmaku[1].meta.pool, Some(1));
+
+        assert_eq!(danmaku[2].content, "飞");
+        assert!(matches!(danmaku[2].mode, DanmakuMode::Positioned(_)));
+    }
+
+    #[test]
+    fn test_extend_queue() {
+        let mut queue = DanmakuQueue::new();
+        let added = extend_queue(&mut queue, [SEGMENT_2, SEGMENT_1]).unwrap();
+        assert_eq!(added, 5);
+
+        let starts: Vec<f64> = queue.danmaku().iter().map(|d| d.start).collect();
+        assert_eq!(starts, [800.0, 1500.0, 3000.0, 360500.0, 361000.0]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut queue = DanmakuQueue::new();
+        let result = extend_queue(&mut queue, [SEGMENT_1, b"\x0a\xff".as_slice()]);
+        assert!(matches!(result, Err(ParseError::Decode(_))));
+        assert!(queue.danmaku().is_empty());
+
+        let broken = DmSegMobileReply {
+            elems: vec![DanmakuElem {
+                id: 7,
+                mode: 7,
+                content: "not json".to_string(),
+                ..Default::default()
+            }],
+        };
+        assert!(matches!(
+            parse_slice(&broken.encode_to_vec()),
+            Err(ParseError::Entry { id: 7, .. })
+        ));
+    }
+}
@@ -0,0 +1,755 @@
+//! danmakw's binary track cache, so a parsed track opens without parsing the
+//! original file again. All numbers are little-endian.
+//!
+//! ```text
+//! header    32 bytes: "DMKW", version u16, optional column flags u16,
+//!           count u32, string table length u32, extra section length u32,
+//!           scroll and center duration f32 (NaN when unset), reserved u32
+//! columns   start f64, content (offset u32, len u32), color RGBA, mode u8,
+//!           then every optional column that is flagged, as a presence
+//!           bitmap followed by one value per danmaku
+//! strings   UTF-8, shared by every string reference and deduplicated
+//! extra     motion specs, spans and extras of the danmaku that have them
+//! ```
+//!
+//! Danmaku are stored sorted by `start`. [`CacheReader`] reads the columns
+//! in place, so only the danmaku that are asked for get allocated.
+
+use std::{
+    collections::HashMap,
+    io::{
+        self,
+        Write,
+    },
+};
+
+use thiserror::Error;
+
+use crate::{
+    AlphaKeyframe,
+    Color,
+    Danmaku,
+    DanmakuMeta,
+    DanmakuMode,
+    DanmakuQueue,
+    MotionSpec,
+    PathPoint,
+    TextSpan,
+    TrackDurations,
+    danmaku::is_valid_size,
+};
+
+pub const MAGIC: [u8; 4] = *b"DMKW";
+pub const VERSION: u16 = 1;
+
+const HEADER_LEN: usize = 32;
+
+// Optional columns, in file order
+const SIZE: u16 = 1 << 0;
+const ID: u16 = 1 << 1;
+const SENDER: u16 = 1 << 2;
+const SEND_TIME: u16 = 1 << 3;
+const POOL: u16 = 1 << 4;
+const WEIGHT: u16 = 1 << 5;
+const SOURCE: u16 = 1 << 6;
+const EXTRA: u16 = 1 << 7;
+
+const OPTIONAL_COLUMNS: [(u16, usize); 8] = [
+    (SIZE, 4),
+    (ID, 8),
+    (SENDER, 8),
+    (SEND_TIME, 8),
+    (POOL, 4),
+    (WEIGHT, 1),
+    (SOURCE, 8),
+    (EXTRA, 4),
+];
+
+// Parts of an extra record
+const HAS_MOTION: u8 = 1 << 0;
+const HAS_SPANS: u8 = 1 << 1;
+const HAS_EXTRAS: u8 = 1 << 2;
+
+// Stands in for `None` where a string reference is optional
+const NO_STRING: u32 = u32::MAX;
+
+#[derive(Error, Debug)]
+pub enum CacheError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a danmakw track cache")]
+    BadMagic,
+    #[error("Unsupported track cache version {0}, expected {VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("Track cache is truncated")]
+    Truncated,
+    #[error("Corrupt track cache: {0}")]
+    Corrupt(&'static str),
+    #[error("No danmaku at index {0} in the track cache")]
+    OutOfRange(usize),
+}
+
+pub fn write<W: Write>(
+    mut writer: W, danmaku: &[Danmaku], durations: Option<TrackDurations>,
+) -> io::Result<()> {
+    writer.write_all(&to_bytes(danmaku, durations))?;
+    writer.flush()
+}
+
+pub fn to_bytes(danmaku: &[Danmaku], durations: Option<TrackDurations>) -> Vec<u8> {
+    let mut sorted: Vec<&Danmaku> = danmaku.iter().collect();
+    sorted.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+    let mut strings = StringTable::default();
+    let mut extra = Vec::new();
+
+    let mut start = Vec::with_capacity(sorted.len() * 8);
+    let mut content = Vec::with_capacity(sorted.len() * 8);
+    let mut color = Vec::with_capacity(sorted.len() * 4);
+    let mut mode = Vec::with_capacity(sorted.len());
+    let mut optional: Vec<Column> = OPTIONAL_COLUMNS
+        .iter()
+        .map(|&(_, width)| Column::new(sorted.len(), width))
+        .collect();
+
+    for (index, danmaku) in sorted.iter().enumerate() {
+        start.extend_from_slice(&danmaku.start.to_le_bytes());
+        content.extend_from_slice(&strings.add(&danmaku.content));
+        let Color { r, g, b, a } = danmaku.color;
+        color.extend_from_slice(&[r, g, b, a]);
+        mode.push(mode_code(&danmaku.mode));
+
+        let meta = &danmaku.meta;
+        optional[0].set(index, danmaku.size.map(f32::to_le_bytes));
+        optional[1].set(index, meta.id.map(u64::to_le_bytes));
+        optional[2].set(index, meta.sender.as_deref().map(|s| strings.add(s)));
+        optional[3].set(index, meta.send_time.map(i64::to_le_bytes));
+        optional[4].set(index, meta.pool.map(u32::to_le_bytes));
+        optional[5].set(index, meta.weight.map(|weight| [weight]));
+        optional[6].set(index, meta.source.as_deref().map(|s| strings.add(s)));
+
+        let offset = write_extra(&mut extra, &mut strings, danmaku);
+        optional[7].set(index, offset.map(u32::to_le_bytes));
+    }
+
+    let flags = OPTIONAL_COLUMNS
+        .iter()
+        .zip(&optional)
+        .filter(|(_, column)| column.used)
+        .fold(0, |flags, (&(flag, _), _)| flags | flag);
+
+    let (scroll, center) = durations.map_or((f32::NAN, f32::NAN), |d| (d.scroll, d.center));
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&flags.to_le_bytes());
+    bytes.extend_from_slice(&(sorted.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(strings.data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(extra.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&scroll.to_le_bytes());
+    bytes.extend_from_slice(&center.to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+
+    bytes.extend_from_slice(&start);
+    bytes.extend_from_slice(&content);
+    bytes.extend_from_slice(&color);
+    bytes.extend_from_slice(&mode);
+    for column in optional.iter().filter(|column| column.used) {
+        bytes.extend_from_slice(&column.bitmap);
+        bytes.extend_from_slice(&column.values);
+    }
+    bytes.extend_from_slice(strings.data.as_bytes());
+    bytes.extend_from_slice(&extra);
+
+    bytes
+}
+
+struct Column {
+    bitmap: Vec<u8>,
+    values: Vec<u8>,
+    width: usize,
+    used: bool,
+}
+
+impl Column {
+    fn new(count: usize, width: usize) -> Self {
+        Self {
+            bitmap: vec![0; count.div_ceil(8)],
+            values: vec![0; count * width],
+            width,
+            used: false,
+        }
+    }
+
+    fn set<const N: usize>(&mut self, index: usize, value: Option<[u8; N]>) {
+        if let Some(value) = value {
+            self.bitmap[index / 8] |= 1 << (index % 8);
+            self.values[index * self.width..(index + 1) * self.width].copy_from_slice(&value);
+            self.used = true;
+        }
+    }
+}
+
+#[derive(Default)]
+struct StringTable {
+    data: String,
+    offsets: HashMap<String, u32>,
+}
+
+impl StringTable {
+    // Offset and length, as stored in the columns
+    fn add(&mut self, string: &str) -> [u8; 8] {
+        let offset = match self.offsets.get(string) {
+            Some(&offset) => offset,
+            None => {
+                let offset = self.data.len() as u32;
+                self.data.push_str(string);
+                self.offsets.insert(string.to_string(), offset);
+                offset
+            }
+        };
+
+        let mut reference = [0; 8];
+        reference[..4].copy_from_slice(&offset.to_le_bytes());
+        reference[4..].copy_from_slice(&(string.len() as u32).to_le_bytes());
+        reference
+    }
+
+    fn add_optional(&mut self, string: Option<&str>) -> [u8; 8] {
+        match string {
+            Some(string) => self.add(string),
+            None => {
+                let mut reference = [0; 8];
+                reference[..4].copy_from_slice(&NO_STRING.to_le_bytes());
+                reference
+            }
+        }
+    }
+}
+
+fn mode_code(mode: &DanmakuMode) -> u8 {
+    match mode {
+        DanmakuMode::Scroll => 0,
+        DanmakuMode::ReverseScroll => 1,
+        DanmakuMode::TopCenter => 2,
+        DanmakuMode::BottomCenter => 3,
+        DanmakuMode::Positioned(_) => 4,
+    }
+}
+
+// Returns the record's offset, `None` when the danmaku needs none
+fn write_extra(extra: &mut Vec<u8>, strings: &mut StringTable, danmaku: &Danmaku) -> Option<u32> {
+    let motion = match &danmaku.mode {
+        DanmakuMode::Positioned(spec) => Some(spec),
+        _ => None,
+    };
+    let spans = danmaku.spans.as_ref();
+    let extras = &danmaku.meta.extras;
+
+    let parts = motion.map_or(0, |_| HAS_MOTION)
+        | spans.map_or(0, |_| HAS_SPANS)
+        | if extras.is_empty() { 0 } else { HAS_EXTRAS };
+    if parts == 0 {
+        return None;
+    }
+
+    let offset = extra.len() as u32;
+    extra.push(parts);
+
+    if let Some(spec) = motion {
+        extra.extend_from_slice(&spec.duration.to_le_bytes());
+        extra.extend_from_slice(&(spec.path.len() as u32).to_le_bytes());
+        for point in &spec.path {
+            for value in [point.time, point.x, point.y] {
+                extra.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        extra.extend_from_slice(&(spec.alpha.len() as u32).to_le_bytes());
+        for key in &spec.alpha {
+            for value in [key.time, key.alpha] {
+                extra.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        extra.extend_from_slice(&spec.rotate_z.to_le_bytes());
+        extra.extend_from_slice(&spec.rotate_y.to_le_bytes());
+        extra.extend_from_slice(&strings.add_optional(spec.font.as_deref()));
+    }
+
+    if let Some(spans) = spans {
+        extra.extend_from_slice(&(spans.len() as u32).to_le_bytes());
+        for span in spans {
+            extra.extend_from_slice(&strings.add(&span.text));
+            // Flags: color set, weight set, italic
+            let flags = u8::from(span.color.is_some())
+                | u8::from(span.weight.is_some()) << 1
+                | u8::from(span.italic) << 2;
+            extra.push(flags);
+            let Color { r, g, b, a } = span.color.unwrap_or_default();
+            extra.extend_from_slice(&[r, g, b, a]);
+            extra.extend_from_slice(&span.weight.unwrap_or_default().to_le_bytes());
+        }
+    }
+
+    if !extras.is_empty() {
+        extra.extend_from_slice(&(extras.len() as u32).to_le_bytes());
+        for (key, value) in extras {
+            extra.extend_from_slice(&strings.add(key));
+            extra.extend_from_slice(&strings.add(value));
+        }
+    }
+
+    Some(offset)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OptionalColumn {
+    bitmap: usize,
+    values: usize,
+}
+
+/// Reads a cached track in place. Building a [`Danmaku`] only copies its own
+/// strings out of the shared table.
+#[derive(Debug, Clone)]
+pub struct CacheReader<'a> {
+    bytes: &'a [u8],
+    count: usize,
+    durations: Option<TrackDurations>,
+    start: usize,
+    content: usize,
+    color: usize,
+    mode: usize,
+    optional: [Option<OptionalColumn>; 8],
+    strings: &'a str,
+    extra: &'a [u8],
+}
+
+impl<'a> CacheReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, CacheError> {
+        let mut header = Cursor::new(bytes);
+        if header.take(4)? != MAGIC {
+            return Err(CacheError::BadMagic);
+        }
+
+        let version = header.u16()?;
+        if version != VERSION {
+            return Err(CacheError::UnsupportedVersion(version));
+        }
+
+        let flags = header.u16()?;
+        let count = header.u32()? as usize;
+        let strings_len = header.u32()? as usize;
+        let extra_len = header.u32()? as usize;
+        let scroll = header.f32()?;
+        let center = header.f32()?;
+        let durations = (!scroll.is_nan() && !center.is_nan())
+            .then_some(TrackDurations { scroll, center });
+
+        let mut offset = HEADER_LEN;
+        let mut section = |len: usize| {
+            let start = offset;
+            offset = offset.checked_add(len).ok_or(CacheError::Truncated)?;
+            if offset > bytes.len() {
+                return Err(CacheError::Truncated);
+            }
+            Ok(start)
+        };
+
+        let start = section(count * 8)?;
+        let content = section(count * 8)?;
+        let color = section(count * 4)?;
+        let mode = section(count)?;
+
+        let mut optional = [None; 8];
+        for (slot, &(flag, width)) in optional.iter_mut().zip(&OPTIONAL_COLUMNS) {
+            if flags & flag != 0 {
+                *slot = Some(OptionalColumn {
+                    bitmap: section(count.div_ceil(8))?,
+                    values: section(count * width)?,
+                });
+            }
+        }
+
+        let strings = section(strings_len)?;
+        let strings = std::str::from_utf8(&bytes[strings..strings + strings_len])
+            .map_err(|_| CacheError::Corrupt("string table is not UTF-8"))?;
+        let extra = section(extra_len)?;
+        let extra = &bytes[extra..extra + extra_len];
+
+        Ok(Self {
+            bytes,
+            count,
+            durations,
+            start,
+            content,
+            color,
+            mode,
+            optional,
+            strings,
+            extra,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// `None` when the track uses the renderer's defaults.
+    pub fn durations(&self) -> Option<TrackDurations> {
+        self.durations
+    }
+
+    pub fn get(&self, index: usize) -> Result<Danmaku, CacheError> {
+        if index >= self.count {
+            return Err(CacheError::OutOfRange(index));
+        }
+
+        let [r, g, b, a] = self.array(self.color + index * 4);
+        let size = self
+            .value(0, index)
+            .map(f32::from_le_bytes)
+            .filter(|&size| is_valid_size(size));
+
+        let meta = DanmakuMeta {
+            id: self.value(1, index).map(u64::from_le_bytes),
+            sender: self.optional_string(2, index)?,
+            send_time: self.value(3, index).map(i64::from_le_bytes),
+            pool: self.value(4, index).map(u32::from_le_bytes),
+            weight: self.value(5, index).map(|[weight]| weight),
+            source: self.optional_string(6, index)?,
+            ..Default::default()
+        };
+
+        let mut danmaku = Danmaku {
+            content: self.content(index)?.to_string(),
+            start: self.start(index),
+            color: Color { r, g, b, a },
+            size,
+            meta,
+            ..Default::default()
+        };
+
+        let motion = match self.value(7, index).map(u32::from_le_bytes) {
+            Some(offset) => self.read_extra(offset as usize, &mut danmaku)?,
+            None => None,
+        };
+
+        danmaku.mode = match (self.bytes[self.mode + index], motion) {
+            (0, _) => DanmakuMode::Scroll,
+            (1, _) => DanmakuMode::ReverseScroll,
+            (2, _) => DanmakuMode::TopCenter,
+            (3, _) => DanmakuMode::BottomCenter,
+            (4, Some(spec)) => DanmakuMode::Positioned(Box::new(spec)),
+            (4, None) => return Err(CacheError::Corrupt("positioned danmaku without motion")),
+            _ => return Err(CacheError::Corrupt("unknown mode")),
+        };
+
+        Ok(danmaku)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Result<Danmaku, CacheError>> + '_ {
+        (0..self.count).map(|index| self.get(index))
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<Danmaku>, CacheError> {
+        self.iter().collect()
+    }
+
+    // Danmaku are stored sorted, so they go into the queue in the order read
+    pub fn to_queue(&self) -> Result<DanmakuQueue, CacheError> {
+        let sorted = (1..self.count).all(|index| {
+            let (previous, start) = (self.start(index - 1), self.start(index));
+            previous.total_cmp(&start).is_le()
+        });
+        if !sorted {
+            return Err(CacheError::Corrupt("danmaku are not sorted"));
+        }
+
+        let track = self.iter().collect::<Result<_, _>>()?;
+        Ok(DanmakuQueue::from_sorted(track))
+    }
+
+    // The column accessors below expect `index < count`
+    fn start(&self, index: usize) -> f64 {
+        f64::from_le_bytes(self.array(self.start + index * 8))
+    }
+
+    fn content(&self, index: usize) -> Result<&'a str, CacheError> {
+        self.string(self.array(self.content + index * 8))
+    }
+
+    // Offsets were checked against the length in `new`
+    fn array<const N: usize>(&self, offset: usize) -> [u8; N] {
+        self.bytes[offset..offset + N].try_into().expect("slice has N bytes")
+    }
+
+    fn value<const N: usize>(&self, column: usize, index: usize) -> Option<[u8; N]> {
+        let OptionalColumn { bitmap, values } = self.optional[column]?;
+        let present = self.bytes[bitmap + index / 8] & (1 << (index % 8)) != 0;
+        present.then(|| self.array(values + index * N))
+    }
+
+    fn optional_string(&self, column: usize, index: usize) -> Result<Option<String>, CacheError> {
+        self.value(column, index)
+            .map(|reference| self.string(reference).map(str::to_string))
+            .transpose()
+    }
+
+    fn string(&self, reference: [u8; 8]) -> Result<&'a str, CacheError> {
+        let mut cursor = Cursor::new(&reference);
+        let offset = cursor.u32()? as usize;
+        let len = cursor.u32()? as usize;
+
+        offset
+            .checked_add(len)
+            .and_then(|end| self.strings.get(offset..end))
+            .ok_or(CacheError::Corrupt("string reference out of bounds"))
+    }
+
+    // Fills in spans and extras, the motion spec is returned for the mode
+    fn read_extra(
+        &self, offset: usize, danmaku: &mut Danmaku,
+    ) -> Result<Option<MotionSpec>, CacheError> {
+        let mut extra = Cursor::new(self.extra.get(offset..).ok_or(CacheError::Truncated)?);
+        let parts = extra.u8()?;
+
+        let mut motion = None;
+        if parts & HAS_MOTION != 0 {
+            let duration = extra.f32()?;
+            let path = (0..extra.u32()?)
+                .map(|_| {
+                    Ok(PathPoint {
+                        time: extra.f32()?,
+                        x: extra.f32()?,
+                        y: extra.f32()?,
+                    })
+                })
+                .collect::<Result<_, CacheError>>()?;
+            let alpha = (0..extra.u32()?)
+                .map(|_| {
+                    Ok(AlphaKeyframe {
+                        time: extra.f32()?,
+                        alpha: extra.f32()?,
+                    })
+                })
+                .collect::<Result<_, CacheError>>()?;
+            let rotate_z = extra.f32()?;
+            let rotate_y = extra.f32()?;
+            let font = extra.array::<8>()?;
+            let font = if font[..4] == NO_STRING.to_le_bytes() {
+                None
+            } else {
+                Some(self.string(font)?.to_string())
+            };
+
+            motion = Some(MotionSpec {
+                duration,
+                path,
+                alpha,
+                rotate_z,
+                rotate_y,
+                font,
+            });
+        }
+
+        if parts & HAS_SPANS != 0 {
+            let spans = (0..extra.u32()?)
+                .map(|_| {
+                    let text = self.string(extra.array()?)?.to_string();
+                    let flags = extra.u8()?;
+                    let [r, g, b, a] = extra.array()?;
+                    let weight = extra.u16()?;
+
+                    Ok(TextSpan {
+                        text,
+                        color: (flags & 1 != 0).then_some(Color { r, g, b, a }),
+                        weight: (flags & 2 != 0).then_some(weight),
+                        italic: flags & 4 != 0,
+                    })
+                })
+                .collect::<Result<_, CacheError>>()?;
+            danmaku.spans = Some(spans);
+        }
+
+        if parts & HAS_EXTRAS != 0 {
+            for _ in 0..extra.u32()? {
+                let key = self.string(extra.array()?)?;
+                let value = self.string(extra.array()?)?;
+                danmaku
+                    .meta
+                    .extras
+                    .insert(key.to_string(), value.to_string());
+            }
+        }
+
+        Ok(motion)
+    }
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CacheError> {
+        if self.bytes.len() < len {
+            return Err(CacheError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CacheError> {
+        Ok(self.take(N)?.try_into().expect("slice has N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, CacheError> {
+        self.array().map(|[byte]| byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, CacheError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, CacheError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Result<f32, CacheError> {
+        self.array().map(f32::from_le_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut danmaku: Vec<Danmaku>) -> Vec<Danmaku> {
+        danmaku.sort_by(|a, b| a.start.total_cmp(&b.start));
+        danmaku
+    }
+
+    #[cfg(feature = "bilibili")]
+    #[test]
+    fn test_round_trip_xml() {
+        use crate::formats::bilibili;
+
+        for xml in [
+            include_str!("../../tests/fixtures/bilibili/basic.xml"),
+            include_str!("../../tests/fixtures/bilibili/positioned.xml"),
+        ] {
+            let parsed = bilibili::Parser::new().lenient(true).parse_str(xml).unwrap();
+            let bytes = to_bytes(&parsed.danmaku, None);
+
+            let reader = CacheReader::new(&bytes).unwrap();
+            assert_eq!(reader.len(), parsed.danmaku.len());
+            assert_eq!(reader.durations(), None);
+            assert_eq!(reader.to_vec().unwrap(), sorted(parsed.danmaku));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_everything() {
+        let mut meta = DanmakuMeta {
+            id: Some(7),
+            sender: Some("abc".to_string()),
+            send_time: Some(-1),
+            pool: Some(2),
+            weight: Some(9),
+            source: Some("nico".to_string()),
+            ..Default::default()
+        };
+        meta.extras.insert("mail".to_string(), "ue big".to_string());
+
+        let danmaku = vec![
+            Danmaku {
+                content: "later".to_string(),
+                start: 500.0,
+                size: Some(1.5),
+                meta,
+                ..Default::default()
+            }
+            .with_spans(vec![
+                TextSpan {
+                    text: "la".to_string(),
+                    color: Some(Color::from_rgb(0xFF0000)),
+                    ..Default::default()
+                },
+                TextSpan {
+                    text: "ter".to_string(),
+                    weight: Some(700),
+                    italic: true,
+                    ..Default::default()
+                },
+            ]),
+            Danmaku {
+                content: "first".to_string(),
+                start: 100.0,
+                mode: DanmakuMode::BottomCenter,
+                ..Default::default()
+            },
+            Danmaku {
+                content: "first".to_string(),
+                start: 100.0,
+                mode: DanmakuMode::ReverseScroll,
+                ..Default::default()
+            },
+        ];
+        let durations = TrackDurations {
+            scroll: 4000.0,
+            center: 3000.0,
+        };
+
+        let mut bytes = Vec::new();
+        write(&mut bytes, &danmaku, Some(durations)).unwrap();
+
+        let reader = CacheReader::new(&bytes).unwrap();
+        assert_eq!(reader.durations(), Some(durations));
+        assert_eq!(reader.get(0).unwrap().content, "first");
+        assert_eq!(reader.get(2).unwrap().start, 500.0);
+        assert!(matches!(reader.get(3), Err(CacheError::OutOfRange(3))));
+
+        let mut queue = reader.to_queue().unwrap();
+        assert_eq!(queue.danmaku(), sorted(danmaku));
+        assert_eq!(queue.pop_to_time(200.0).len(), 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        let track: Vec<Danmaku> = (0..5).map(|i| Danmaku::at("a", i as f64 * 100.0)).collect();
+        let bytes = to_bytes(&track, None);
+
+        assert!(matches!(CacheReader::new(b"<i></i>"), Err(CacheError::BadMagic)));
+        assert!(matches!(
+            CacheReader::new(&bytes[..bytes.len() - 1]),
+            Err(CacheError::Truncated)
+        ));
+
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(
+            CacheReader::new(&newer),
+            Err(CacheError::UnsupportedVersion(2))
+        ));
+
+        let count = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+        let mut bad_mode = bytes.clone();
+        bad_mode[HEADER_LEN + count * 20] = 9;
+        let reader = CacheReader::new(&bad_mode).unwrap();
+        assert!(matches!(reader.get(0), Err(CacheError::Corrupt(_))));
+
+        let mut unsorted = bytes.clone();
+        unsorted[HEADER_LEN..HEADER_LEN + 8].copy_from_slice(&f64::MAX.to_le_bytes());
+        let reader = CacheReader::new(&unsorted).unwrap();
+        assert!(matches!(reader.to_queue(), Err(CacheError::Corrupt(_))));
+    }
+}
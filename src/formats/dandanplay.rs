@@ -0,0 +1,153 @@
+//! Dandanplay-compatible comment API responses
+//! (`{"count": n, "comments": [{"cid": 1, "p": "...", "m": "text"}]}`).
+
+use serde_json::Value;
+use thiserror::Error;
+
+use super::bilibili::{
+    EntryError,
+    mode_from_code,
+};
+use crate::{
+    Color,
+    Danmaku,
+    DanmakuMeta,
+    DanmakuMode,
+};
+
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("JSON parsing error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Expected a comment array or an object with 'comments'")]
+    UnknownLayout,
+    #[error("Invalid comment at index {index}: {source}")]
+    Entry {
+        index: usize,
+        #[source]
+        source: EntryError,
+    },
+}
+
+/// Accepts the full response as well as a bare comment array.
+pub fn parse_str(json: &str) -> Result<Vec<Danmaku>, ParseError> {
+    parse_value(serde_json::from_str(json)?)
+}
+
+pub fn parse_slice(json: &[u8]) -> Result<Vec<Danmaku>, ParseError> {
+    parse_value(serde_json::from_slice(json)?)
+}
+
+pub fn parse_reader<R: std::io::Read>(reader: R) -> Result<Vec<Danmaku>, ParseError> {
+    parse_value(serde_json::from_reader(reader)?)
+}
+
+fn parse_value(value: Value) -> Result<Vec<Danmaku>, ParseError> {
+    let comments = match &value {
+        Value::Array(comments) => comments,
+        Value::Object(object) => object
+            .get("comments")
+            .and_then(Value::as_array)
+            .ok_or(ParseError::UnknownLayout)?,
+        _ => return Err(ParseError::UnknownLayout),
+    };
+
+    comments
+        .iter()
+        .enumerate()
+        .map(|(index, comment)| {
+            parse_comment(comment).map_err(|source| ParseError::Entry { index, source })
+        })
+        .collect()
+}
+
+// p = "time,mode,color,uid", time in seconds and the rest as in Bilibili dumps
+fn parse_comment(comment: &Value) -> Result<Danmaku, EntryError> {
+    let p = comment
+        .get("p")
+        .and_then(Value::as_str)
+        .ok_or(EntryError::MissingP)?;
+
+    let parts: Vec<&str> = p.split(',').map(str::trim).collect();
+    if parts.len() < 3 {
+        return Err(EntryError::InvalidPFormat(p.to_string()));
+    }
+
+    let start: f64 = parts[0].parse()?;
+    let mode_val: u8 = parts[1].parse()?;
+    let color_val: u32 = parts[2].parse()?;
+
+    let meta = DanmakuMeta {
+        id: comment.get("cid").and_then(Value::as_u64),
+        sender: parts.get(3).map(|uid| uid.to_string()),
+        ..Default::default()
+    };
+
+    Ok(Danmaku {
+        content: comment
+            .get("m")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        start: start * 1000.0,
+        color: Color::from_rgb(color_val),
+        mode: mode_from_code(mode_val).unwrap_or(DanmakuMode::Scroll),
+        meta,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMENTS: &str = include_str!("../../tests/fixtures/dandanplay/comments.json");
+    const BARE: &str = include_str!("../../tests/fixtures/dandanplay/bare.json");
+
+    #[test]
+    fn test_parse_response() {
+        let danmaku = parse_str(COMMENTS).unwrap();
+        assert_eq!(danmaku.len(), 4);
+
+        assert_eq!(danmaku[0].content, "前排");
+        assert_eq!(danmaku[0].start, 1500.0);
+        assert_eq!(danmaku[0].mode, DanmakuMode::Scroll);
+        assert_eq!(danmaku[0].color, Color::from_rgb(0xFFFFFF));
+        assert_eq!(danmaku[0].meta.id, Some(1001));
+        assert_eq!(danmaku[0].meta.sender.as_deref(), Some("[BiliBili]3a8c9f2b"));
+
+        assert_eq!(danmaku[1].mode, DanmakuMode::TopCenter);
+        assert_eq!(danmaku[1].color, Color::from_rgb(0xFF0000));
+        assert_eq!(danmaku[2].mode, DanmakuMode::BottomCenter);
+        assert_eq!(danmaku[2].meta.sender.as_deref(), Some("[Gamer]user"));
+
+        // Unknown modes scroll, like in the Bilibili parser
+        assert_eq!(danmaku[3].mode, DanmakuMode::Scroll);
+    }
+
+    #[test]
+    fn test_parse_bare_array() {
+        let danmaku = parse_slice(BARE.as_bytes()).unwrap();
+        assert_eq!(danmaku.len(), 2);
+        assert_eq!(danmaku[1].start, 62000.0);
+        assert_eq!(danmaku[1].mode, DanmakuMode::ReverseScroll);
+        assert_eq!(danmaku[1].meta.sender, None);
+    }
+
+    #[test]
+    fn test_invalid_comment() {
+        let err = parse_str(r#"[{"p": "1.0,1,0", "m": "a"}, {"p": "1.0", "m": "b"}]"#).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::Entry {
+                index: 1,
+                source: EntryError::InvalidPFormat(_)
+            }
+        ));
+
+        assert!(matches!(
+            parse_str(r#"{"count": 0}"#),
+            Err(ParseError::UnknownLayout)
+        ));
+    }
+}
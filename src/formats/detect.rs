@@ -0,0 +1,440 @@
+//! Picks a parser for comment files of unknown origin, see [`load_any`].
+
+use std::fmt;
+
+use thiserror::Error;
+
+#[cfg(feature = "ass")]
+use super::ass;
+#[cfg(feature = "bilibili")]
+use super::bilibili;
+#[cfg(feature = "bilibili-protobuf")]
+use super::bilibili_protobuf;
+#[cfg(feature = "cache")]
+use super::cache;
+#[cfg(feature = "dandanplay")]
+use super::dandanplay;
+#[cfg(feature = "serde")]
+use super::jsonl;
+#[cfg(feature = "niconico")]
+use super::niconico;
+use crate::{
+    Danmaku,
+    TrackDurations,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    #[cfg(feature = "bilibili")]
+    Bilibili,
+    #[cfg(feature = "bilibili-protobuf")]
+    BilibiliProtobuf,
+    #[cfg(feature = "niconico")]
+    Niconico,
+    #[cfg(feature = "dandanplay")]
+    Dandanplay,
+    #[cfg(feature = "ass")]
+    Ass,
+    #[cfg(feature = "serde")]
+    Jsonl,
+    #[cfg(feature = "cache")]
+    Cache,
+}
+
+impl Format {
+    // In the order they are tried when sniffing fails
+    pub const ALL: &[Format] = &[
+        #[cfg(feature = "cache")]
+        Format::Cache,
+        #[cfg(feature = "serde")]
+        Format::Jsonl,
+        #[cfg(feature = "bilibili")]
+        Format::Bilibili,
+        #[cfg(feature = "niconico")]
+        Format::Niconico,
+        #[cfg(feature = "dandanplay")]
+        Format::Dandanplay,
+        #[cfg(feature = "ass")]
+        Format::Ass,
+        #[cfg(feature = "bilibili-protobuf")]
+        Format::BilibiliProtobuf,
+    ];
+
+    // On-screen durations the source expects, `None` for the renderer's defaults
+    pub fn durations(self) -> Option<TrackDurations> {
+        match self {
+            #[cfg(feature = "niconico")]
+            Format::Niconico => Some(niconico::DURATIONS),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    fn parse(self, bytes: &[u8]) -> Result<Vec<Danmaku>, FormatError> {
+        #[allow(unused_variables)]
+        let text = || std::str::from_utf8(bytes).map_err(|_| FormatError::NotUtf8);
+
+        match self {
+            #[cfg(feature = "bilibili")]
+            Format::Bilibili => {
+                let parsed = bilibili::Parser::new().repair(true).parse_slice(bytes)?;
+                Ok(parsed.danmaku)
+            }
+            #[cfg(feature = "bilibili-protobuf")]
+            Format::BilibiliProtobuf => Ok(bilibili_protobuf::parse_slice(bytes)?),
+            #[cfg(feature = "niconico")]
+            Format::Niconico => {
+                let text = text()?;
+                if looks_like_json(text) {
+                    Ok(niconico::parse_json(text)?)
+                } else {
+                    Ok(niconico::parse_xml(text)?)
+                }
+            }
+            #[cfg(feature = "dandanplay")]
+            Format::Dandanplay => Ok(dandanplay::parse_slice(bytes)?),
+            #[cfg(feature = "ass")]
+            Format::Ass => Ok(ass::parse_str(text()?)?),
+            #[cfg(feature = "serde")]
+            Format::Jsonl => Ok(jsonl::read(bytes)?.danmaku),
+            #[cfg(feature = "cache")]
+            Format::Cache => Ok(cache::CacheReader::new(bytes)?.to_vec()?),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            #[cfg(feature = "bilibili")]
+            Format::Bilibili => "Bilibili XML",
+            #[cfg(feature = "bilibili-protobuf")]
+            Format::BilibiliProtobuf => "Bilibili protobuf",
+            #[cfg(feature = "niconico")]
+            Format::Niconico => "Niconico",
+            #[cfg(feature = "dandanplay")]
+            Format::Dandanplay => "dandanplay JSON",
+            #[cfg(feature = "ass")]
+            Format::Ass => "ASS",
+            #[cfg(feature = "serde")]
+            Format::Jsonl => "danmakw JSONL",
+            #[cfg(feature = "cache")]
+            Format::Cache => "danmakw track cache",
+        };
+
+        f.write_str(name)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FormatError {
+    #[error("Not valid UTF-8")]
+    NotUtf8,
+    #[error("No danmaku found")]
+    Empty,
+    #[cfg(feature = "bilibili")]
+    #[error(transparent)]
+    Bilibili(#[from] bilibili::ParseError),
+    #[cfg(feature = "bilibili-protobuf")]
+    #[error(transparent)]
+    BilibiliProtobuf(#[from] bilibili_protobuf::ParseError),
+    #[cfg(feature = "niconico")]
+    #[error(transparent)]
+    Niconico(#[from] niconico::ParseError),
+    #[cfg(feature = "dandanplay")]
+    #[error(transparent)]
+    Dandanplay(#[from] dandanplay::ParseError),
+    #[cfg(feature = "ass")]
+    #[error(transparent)]
+    Ass(#[from] ass::ParseError),
+    #[cfg(feature = "serde")]
+    #[error(transparent)]
+    Jsonl(#[from] jsonl::JsonlError),
+    #[cfg(feature = "cache")]
+    #[error(transparent)]
+    Cache(#[from] cache::CacheError),
+}
+
+/// None of the parsers that were tried accepted the input.
+#[derive(Error, Debug)]
+pub struct DetectError {
+    pub attempts: Vec<(Format, FormatError)>,
+}
+
+impl fmt::Display for DetectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Unrecognised danmaku format, tried ")?;
+        for (index, (format, error)) in self.attempts.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{format} ({error})")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sniffs the format of a comment file and parses it with the matching
+/// parser. When the content isn't recognised every enabled parser is tried.
+pub fn load_any(bytes: &[u8]) -> Result<(Format, Vec<Danmaku>), DetectError> {
+    let mut attempts = Vec::new();
+    let sniffed = sniff(bytes);
+
+    if let Some(format) = sniffed {
+        match format.parse(bytes) {
+            Ok(danmaku) => return Ok((format, danmaku)),
+            Err(e) => attempts.push((format, e)),
+        }
+    }
+
+    // Lenient parsers happily read nothing out of foreign input
+    for &format in Format::ALL.iter().filter(|&&format| Some(format) != sniffed) {
+        match format.parse(bytes) {
+            Ok(danmaku) if !danmaku.is_empty() => return Ok((format, danmaku)),
+            Ok(_) => attempts.push((format, FormatError::Empty)),
+            Err(e) => attempts.push((format, e)),
+        }
+    }
+
+    Err(DetectError { attempts })
+}
+
+/// Guesses the format from the start of the content without parsing it.
+pub fn sniff(bytes: &[u8]) -> Option<Format> {
+    #[cfg(feature = "cache")]
+    if bytes.starts_with(&cache::MAGIC) {
+        return Some(Format::Cache);
+    }
+
+    #[cfg(feature = "bilibili-protobuf")]
+    if is_protobuf_segment(bytes) {
+        return Some(Format::BilibiliProtobuf);
+    }
+
+    let text = String::from_utf8_lossy(&bytes[..bytes.len().min(SNIFF_LEN)]);
+    let text = text.trim_start_matches('\u{FEFF}').trim_start();
+
+    if text.starts_with('<') {
+        return sniff_xml(text);
+    }
+
+    let json = if text.starts_with('{') || text.starts_with('[') {
+        sniff_json(text)
+    } else {
+        None
+    };
+
+    json.or_else(|| sniff_ass(text))
+}
+
+// Enough to get past XML prologues and the first JSON entries
+const SNIFF_LEN: usize = 4096;
+
+#[allow(unused_variables)]
+fn sniff_xml(text: &str) -> Option<Format> {
+    let mut rest = text;
+
+    // Skip the declaration, comments and doctype up to the root element
+    loop {
+        rest = rest.trim_start();
+        let skip_to = if rest.starts_with("<?") {
+            "?>"
+        } else if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<!") {
+            ">"
+        } else {
+            break;
+        };
+
+        let end = rest.find(skip_to)?;
+        rest = &rest[end + skip_to.len()..];
+    }
+
+    let root: String = rest
+        .strip_prefix('<')?
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | ':'))
+        .collect();
+
+    match root.as_str() {
+        #[cfg(feature = "bilibili")]
+        "i" => Some(Format::Bilibili),
+        #[cfg(feature = "niconico")]
+        "packet" => Some(Format::Niconico),
+        _ => None,
+    }
+}
+
+#[allow(unused_variables)]
+fn sniff_json(text: &str) -> Option<Format> {
+    let text = text.trim_start();
+
+    #[cfg(feature = "serde")]
+    if text.starts_with('{') {
+        let header = text.lines().next().unwrap_or_default();
+        let header = serde_json::from_str::<serde_json::Value>(header);
+        if header.is_ok_and(|header| header["format"] == jsonl::FORMAT) {
+            return Some(Format::Jsonl);
+        }
+    }
+
+    // Look at the keys used near the start, the input may be cut off
+    #[cfg(feature = "niconico")]
+    if ["\"chat\"", "\"vposMs\"", "\"threads\"", "\"vpos\""]
+        .iter()
+        .any(|key| text.contains(key))
+    {
+        return Some(Format::Niconico);
+    }
+
+    #[cfg(feature = "dandanplay")]
+    if text.contains("\"p\"") && text.contains("\"m\"") {
+        return Some(Format::Dandanplay);
+    }
+
+    None
+}
+
+#[allow(unused_variables)]
+fn sniff_ass(text: &str) -> Option<Format> {
+    #[cfg(feature = "ass")]
+    {
+        let head = text.to_ascii_lowercase();
+        if head.starts_with("[script info]") || head.contains("\n[events]") {
+            return Some(Format::Ass);
+        }
+    }
+
+    None
+}
+
+// Protobuf has no magic number, but a segment starts with its first element
+// (field 1, length delimited) whose own first field is the id or progress.
+// Text formats can't produce a control character right after that.
+#[cfg(feature = "bilibili-protobuf")]
+fn is_protobuf_segment(bytes: &[u8]) -> bool {
+    let Some((&0x0A, rest)) = bytes.split_first() else {
+        return false;
+    };
+
+    let mut len = 0usize;
+    for (index, &byte) in rest.iter().enumerate().take(5) {
+        len |= usize::from(byte & 0x7F) << (7 * index);
+        if byte & 0x80 == 0 {
+            let elem = &rest[index + 1..];
+            return len <= elem.len() && matches!(elem.first(), Some(0x08 | 0x10));
+        }
+    }
+
+    false
+}
+
+#[cfg(feature = "niconico")]
+fn looks_like_json(text: &str) -> bool {
+    let text = text.trim_start_matches('\u{FEFF}').trim_start();
+    text.starts_with('{') || text.starts_with('[')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_loads(bytes: &[u8], expected: Format) {
+        assert_eq!(sniff(bytes), Some(expected));
+        let (format, danmaku) = load_any(bytes).unwrap();
+        assert_eq!(format, expected);
+        assert!(!danmaku.is_empty());
+    }
+
+    #[cfg(feature = "bilibili")]
+    #[test]
+    fn test_bilibili() {
+        let xml = include_bytes!("../../tests/fixtures/bilibili/basic.xml");
+        assert_loads(xml, Format::Bilibili);
+
+        let mut with_bom = "\u{FEFF}<!-- saved -->\n".as_bytes().to_vec();
+        with_bom.extend_from_slice(xml);
+        assert_loads(&with_bom, Format::Bilibili);
+    }
+
+    #[cfg(feature = "niconico")]
+    #[test]
+    fn test_niconico() {
+        let xml = include_bytes!("../../tests/fixtures/niconico/comments.xml");
+        assert_loads(xml, Format::Niconico);
+        let json = include_bytes!("../../tests/fixtures/niconico/legacy.json");
+        assert_loads(json, Format::Niconico);
+        let json = include_bytes!("../../tests/fixtures/niconico/v1.json");
+        assert_loads(json, Format::Niconico);
+        assert_eq!(Format::Niconico.durations(), Some(niconico::DURATIONS));
+    }
+
+    #[cfg(feature = "dandanplay")]
+    #[test]
+    fn test_dandanplay() {
+        let json = include_bytes!("../../tests/fixtures/dandanplay/comments.json");
+        assert_loads(json, Format::Dandanplay);
+        let json = include_bytes!("../../tests/fixtures/dandanplay/bare.json");
+        assert_loads(json, Format::Dandanplay);
+    }
+
+    #[cfg(feature = "bilibili-protobuf")]
+    #[test]
+    fn test_bilibili_protobuf() {
+        let segment = include_bytes!("../../tests/fixtures/bilibili_protobuf/segment_1.bin");
+        assert_loads(segment, Format::BilibiliProtobuf);
+        assert_eq!(sniff(b"\n<i></i>"), Some(Format::Bilibili));
+    }
+
+    #[cfg(feature = "ass")]
+    #[test]
+    fn test_ass() {
+        assert_loads(include_bytes!("../../tests/fixtures/ass/basic.ass"), Format::Ass);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_jsonl() {
+        assert_loads(include_bytes!("../../tests/fixtures/jsonl/track.jsonl"), Format::Jsonl);
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn test_cache() {
+        let danmaku = Danmaku {
+            content: "cached".to_string(),
+            ..Default::default()
+        };
+        assert_loads(&cache::to_bytes(&[danmaku], None), Format::Cache);
+    }
+
+    #[test]
+    fn test_multibyte_at_sniff_limit() {
+        // "弹" straddles the end of the sniffed prefix
+        let mut json = r#"[{"p":"0,1,25,16777215","m":""#.to_string();
+        json.push_str(&"a".repeat(SNIFF_LEN - 1 - json.len()));
+        json.push_str("弹\"}]");
+
+        assert_eq!(
+            sniff(json.as_bytes()).is_some(),
+            cfg!(feature = "dandanplay")
+        );
+    }
+
+    #[test]
+    fn test_unrecognised() {
+        let err = load_any(b"definitely not danmaku").unwrap_err();
+        let tried: Vec<_> = err.attempts.iter().map(|(format, _)| *format).collect();
+        assert_eq!(tried, Format::ALL);
+    }
+
+    #[cfg(feature = "bilibili")]
+    #[test]
+    fn test_sniffed_parser_fails_first() {
+        let err = load_any(b"<i><d p=\"broken\">a</d></i>").unwrap_err();
+        assert_eq!(err.attempts[0].0, Format::Bilibili);
+        assert_eq!(err.attempts.len(), Format::ALL.len());
+    }
+}
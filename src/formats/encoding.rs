@@ -0,0 +1,245 @@
+use std::{
+    collections::VecDeque,
+    io::{
+        self,
+        BufRead,
+        Read,
+    },
+    ops::Range,
+};
+
+use chardetng::EncodingDetector;
+use encoding_rs::{
+    Decoder,
+    DecoderResult,
+    Encoding,
+    UTF_8,
+};
+
+// Bytes looked at to pick the encoding
+const SNIFF_LEN: usize = 16 * 1024;
+const BUF_LEN: usize = 8 * 1024;
+
+// Converts XML in a legacy encoding to UTF-8 on the fly. The encoding comes
+// from a BOM, then the XML declaration, then charset detection on the first
+// bytes. Undecodable bytes become U+FFFD instead of failing.
+pub(crate) struct Utf8Reader<R> {
+    inner: R,
+    // Created on the first read, once the head has been sniffed
+    decoder: Option<Decoder>,
+    head: Vec<u8>,
+    head_pos: usize,
+    out: Vec<u8>,
+    out_pos: usize,
+    // Bytes written before `out`, and where U+FFFD was written in place of
+    // undecodable bytes
+    out_offset: u64,
+    replaced: VecDeque<u64>,
+    done: bool,
+}
+
+impl<R: BufRead> Utf8Reader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            decoder: None,
+            head: Vec::new(),
+            head_pos: 0,
+            out: Vec::new(),
+            out_pos: 0,
+            out_offset: 0,
+            replaced: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    // Whether undecodable bytes were replaced within `range` of the UTF-8
+    // text. Ranges have to be asked for in order, earlier ones are forgotten.
+    pub(crate) fn replaced_within(&mut self, range: Range<u64>) -> bool {
+        let mut replaced = false;
+        while let Some(&offset) = self.replaced.front().filter(|&&offset| offset < range.end) {
+            replaced |= offset >= range.start;
+            self.replaced.pop_front();
+        }
+        replaced
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        if self.decoder.is_none() {
+            (&mut self.inner).take(SNIFF_LEN as u64).read_to_end(&mut self.head)?;
+            let complete = self.head.len() < SNIFF_LEN;
+            self.decoder = Some(detect(&self.head, complete).new_decoder());
+        }
+        let decoder = self.decoder.as_mut().expect("decoder was just created");
+
+        let from_head = self.head_pos < self.head.len();
+        let src = if from_head {
+            &self.head[self.head_pos..]
+        } else {
+            self.inner.fill_buf()?
+        };
+        let last = src.is_empty();
+
+        self.out_offset += self.out.len() as u64;
+        self.out.resize(BUF_LEN, 0);
+        let (mut read, mut written) = (0, 0);
+        let result = loop {
+            let (result, r, w) = decoder.decode_to_utf8_without_replacement(
+                &src[read..],
+                &mut self.out[written..],
+                last,
+            );
+            read += r;
+            written += w;
+
+            let DecoderResult::Malformed(..) = result else {
+                break result;
+            };
+            self.replaced.push_back(self.out_offset + written as u64);
+            self.out.truncate(written);
+            self.out.extend_from_slice("\u{FFFD}".as_bytes());
+            written = self.out.len();
+            self.out.resize(written.max(BUF_LEN), 0);
+        };
+        self.out.truncate(written);
+        self.out_pos = 0;
+
+        if from_head {
+            self.head_pos += read;
+        } else {
+            self.inner.consume(read);
+        }
+
+        self.done = last && result == DecoderResult::InputEmpty;
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for Utf8Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for Utf8Reader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.out_pos == self.out.len() && !self.done {
+            self.refill()?;
+        }
+
+        Ok(&self.out[self.out_pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.out_pos = (self.out_pos + amt).min(self.out.len());
+    }
+}
+
+fn detect(head: &[u8], complete: bool) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(head) {
+        return encoding;
+    }
+
+    // A multi-byte character may be cut off at the end of the head
+    let utf8 = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => (!complete && e.error_len().is_none()) || mostly_utf8(head),
+    };
+
+    // Old dumps were often re-encoded without touching a UTF-8 declaration
+    match declared_encoding(head) {
+        Some(encoding) if encoding != UTF_8 => encoding,
+        _ if utf8 => UTF_8,
+        _ => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(head, complete);
+            detector.guess(None, true)
+        }
+    }
+}
+
+// A few stray bytes in otherwise valid UTF-8 don't make it another encoding
+fn mostly_utf8(head: &[u8]) -> bool {
+    let (text, _) = UTF_8.decode_without_bom_handling(head);
+    let replaced = text.chars().filter(|&c| c == char::REPLACEMENT_CHARACTER).count();
+    let non_ascii = text.chars().filter(|c| !c.is_ascii()).count() - replaced;
+    non_ascii > replaced
+}
+
+// `<?xml version="1.0" encoding="GBK"?>`
+fn declared_encoding(head: &[u8]) -> Option<&'static Encoding> {
+    let start = head.iter().position(|b| !b.is_ascii_whitespace())?;
+    let declaration = head[start..].strip_prefix(b"<?xml")?;
+    let end = declaration.windows(2).position(|w| w == b"?>")?;
+    let declaration = &declaration[..end];
+
+    let name = declaration.windows(8).position(|w| w == b"encoding")?;
+    let value = declaration[name + 8..].trim_ascii_start().strip_prefix(b"=")?;
+    let value = value.trim_ascii_start();
+    let (&quote, value) = value.split_first().filter(|(q, _)| matches!(q, b'"' | b'\''))?;
+    let value = &value[..value.iter().position(|&b| b == quote)?];
+
+    // A UTF-16 label can't be right if the declaration was readable as ASCII
+    Encoding::for_label(value).filter(|encoding| encoding.is_ascii_compatible())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> String {
+        let mut text = String::new();
+        Utf8Reader::new(bytes).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn test_declared_encoding() {
+        let text = "<?xml version=\"1.0\" encoding='gbk'?><i>你好</i>";
+        let (gbk, _, _) = encoding_rs::GBK.encode(text);
+        assert_eq!(decode(&gbk), text);
+    }
+
+    #[test]
+    fn test_detected_encoding() {
+        let body = "<i><d>こんにちは、弾幕のテストです</d></i>";
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode(body);
+        assert_eq!(decode(&sjis), body);
+
+        // Declared as UTF-8 but saved as Shift-JIS
+        let text = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>{body}");
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode(&text);
+        assert_eq!(decode(&sjis), text);
+    }
+
+    #[test]
+    fn test_utf8_passthrough() {
+        let text = "<i>".to_string() + &"弹幕".repeat(SNIFF_LEN) + "</i>";
+        assert_eq!(decode(text.as_bytes()), text);
+        assert_eq!(decode(b"\xEF\xBB\xBF<i/>"), "<i/>");
+        assert_eq!(decode(b"<i>\xE5\xBC\xB9\xE5\xB9\x95\xFF</i>"), "<i>弹幕\u{FFFD}</i>");
+    }
+
+    #[test]
+    fn test_replaced_within() {
+        let xml = [
+            &b"<i>\xFF</i>"[..],
+            "<i>弹幕弹幕</i>".as_bytes(),
+            b"<i>\xFF\xFF</i>",
+        ]
+        .concat();
+        let mut reader = Utf8Reader::new(&xml[..]);
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "<i>\u{FFFD}</i><i>弹幕弹幕</i><i>\u{FFFD}\u{FFFD}</i>");
+
+        assert!(reader.replaced_within(0..10));
+        assert!(!reader.replaced_within(10..29));
+        assert!(reader.replaced_within(29..text.len() as u64));
+        assert!(!reader.replaced_within(0..text.len() as u64));
+    }
+}
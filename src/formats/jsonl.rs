@@ -0,0 +1,250 @@
+//! danmakw's own JSON Lines track format, meant for caching tracks that were
+//! already normalized (and possibly merged from several sources).
+//!
+//! The first line is a header, every following non-empty line is one
+//! serialized [`Danmaku`]:
+//!
+//! ```text
+//! {"format":"danmakw","version":1,"durations":{"scroll":4000.0,"center":3000.0}}
+//! {"content":"hello","start":1500.0,"color":"#FFFFFFFF","mode":"scroll"}
+//! {"content":"top","start":2000.0,"color":"#FF0000FF","mode":"top_center","size":1.5}
+//! ```
+//!
+//! - `start` is in milliseconds and `color` is `#RRGGBBAA`.
+//! - `mode` is `scroll`, `reverse_scroll`, `top_center`, `bottom_center` or
+//!   `{"positioned": {...}}` with a [`crate::MotionSpec`].
+//! - `size`, `meta` and `spans` are left out when unset.
+//! - `durations` in the header is optional, see [`TrackDurations`].
+
+use std::io::{
+    self,
+    BufRead,
+    Write,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use thiserror::Error;
+
+use crate::{
+    Danmaku,
+    TrackDurations,
+    danmaku::is_valid_size,
+};
+
+pub const FORMAT: &str = "danmakw";
+pub const VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum JsonlError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid JSON on line {line}: {source}")]
+    Json {
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Missing danmakw track header")]
+    MissingHeader,
+    #[error("Unsupported track version {0}, expected {VERSION}")]
+    UnsupportedVersion(u32),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    durations: Option<TrackDurations>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Track {
+    // `None` when the track uses the renderer's defaults
+    pub durations: Option<TrackDurations>,
+    pub danmaku: Vec<Danmaku>,
+}
+
+pub fn write<W: Write>(mut writer: W, track: &Track) -> Result<(), JsonlError> {
+    let header = Header {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        durations: track.durations,
+    };
+
+    writeln!(writer, "{}", serde_json::to_string(&header).map_err(json_error(1))?)?;
+    for (index, danmaku) in track.danmaku.iter().enumerate() {
+        let json = serde_json::to_string(danmaku).map_err(json_error(index + 2))?;
+        writeln!(writer, "{json}")?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read<R: BufRead>(reader: R) -> Result<Track, JsonlError> {
+    let mut lines = reader
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line))
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()));
+
+    let (_, header) = lines.next().ok_or(JsonlError::MissingHeader)?;
+    let header = serde_json::from_str::<Header>(&header?)
+        .ok()
+        .filter(|header| header.format == FORMAT)
+        .ok_or(JsonlError::MissingHeader)?;
+
+    if header.version != VERSION {
+        return Err(JsonlError::UnsupportedVersion(header.version));
+    }
+
+    let danmaku = lines
+        .map(|(line, text)| {
+            let mut danmaku: Danmaku = serde_json::from_str(&text?).map_err(json_error(line))?;
+            danmaku.size = danmaku.size.filter(|&size| is_valid_size(size));
+            Ok(danmaku)
+        })
+        .collect::<Result<_, JsonlError>>()?;
+
+    Ok(Track {
+        durations: header.durations,
+        danmaku,
+    })
+}
+
+pub fn read_str(jsonl: &str) -> Result<Track, JsonlError> {
+    read(jsonl.as_bytes())
+}
+
+pub fn write_string(track: &Track) -> Result<String, JsonlError> {
+    let mut buf = Vec::new();
+    write(&mut buf, track)?;
+    // serde_json only produces UTF-8
+    Ok(String::from_utf8(buf).expect("serde_json output is UTF-8"))
+}
+
+fn json_error(line: usize) -> impl Fn(serde_json::Error) -> JsonlError {
+    move |source| JsonlError::Json { line, source }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Color,
+        DanmakuMeta,
+        DanmakuMode,
+        MotionSpec,
+        PathPoint,
+        TextSpan,
+    };
+
+    const TRACK: &str = include_str!("../../tests/fixtures/jsonl/track.jsonl");
+
+    #[test]
+    fn test_read_fixture() {
+        let track = read_str(TRACK).unwrap();
+        assert_eq!(
+            track.durations,
+            Some(TrackDurations {
+                scroll: 4000.0,
+                center: 3000.0,
+            })
+        );
+        assert_eq!(track.danmaku.len(), 3);
+
+        assert_eq!(
+            track.danmaku[0],
+            Danmaku {
+                content: "hello".to_string(),
+                start: 1500.0,
+                ..Default::default()
+            }
+        );
+        assert_eq!(track.danmaku[1].mode, DanmakuMode::TopCenter);
+        assert_eq!(track.danmaku[1].color, Color::from_rgb(0xFF0000));
+        assert_eq!(track.danmaku[1].size, Some(1.5));
+        assert_eq!(track.danmaku[1].meta.id, Some(42));
+        assert!(matches!(track.danmaku[2].mode, DanmakuMode::Positioned(_)));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut meta = DanmakuMeta {
+            sender: Some("abc".to_string()),
+            ..Default::default()
+        };
+        meta.extras.insert("mail".to_string(), "ue".to_string());
+
+        let track = Track {
+            durations: None,
+            danmaku: vec![
+                Danmaku {
+                    content: "a".to_string(),
+                    start: 10.0,
+                    color: Color {
+                        r: 1,
+                        g: 2,
+                        b: 3,
+                        a: 128,
+                    },
+                    mode: DanmakuMode::ReverseScroll,
+                    meta,
+                    ..Default::default()
+                },
+                Danmaku {
+                    start: 20.0,
+                    mode: DanmakuMode::Positioned(Box::new(MotionSpec {
+                        duration: 1000.0,
+                        path: vec![PathPoint {
+                            time: 0.0,
+                            x: 0.5,
+                            y: 0.5,
+                        }],
+                        alpha: Vec::new(),
+                        rotate_z: 0.0,
+                        rotate_y: 0.0,
+                        font: None,
+                    })),
+                    ..Default::default()
+                }
+                .with_spans(vec![TextSpan {
+                    text: "bold".to_string(),
+                    weight: Some(700),
+                    ..Default::default()
+                }]),
+            ],
+        };
+
+        let jsonl = write_string(&track).unwrap();
+        assert_eq!(jsonl.lines().count(), 3);
+        assert!(jsonl.contains(r##""color":"#01020380""##));
+        assert_eq!(read_str(&jsonl).unwrap(), track);
+    }
+
+    #[test]
+    fn test_header_errors() {
+        assert!(matches!(read_str(""), Err(JsonlError::MissingHeader)));
+        assert!(matches!(
+            read_str(r#"{"content":"a","start":0}"#),
+            Err(JsonlError::MissingHeader)
+        ));
+        assert!(matches!(
+            read_str(r#"{"format":"danmakw","version":2}"#),
+            Err(JsonlError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_line() {
+        let jsonl = "{\"format\":\"danmakw\",\"version\":1}\n\n{\"start\":\"soon\"}\n";
+        assert!(matches!(
+            read_str(jsonl),
+            Err(JsonlError::Json { line: 3, .. })
+        ));
+    }
+}
@@ -0,0 +1,45 @@
+#[cfg(feature = "ass")]
+pub mod ass;
+#[cfg(feature = "bilibili")]
+pub mod bilibili;
+#[cfg(feature = "bilibili-protobuf")]
+pub mod bilibili_protobuf;
+#[cfg(feature = "cache")]
+pub mod cache;
+#[cfg(feature = "dandanplay")]
+pub mod dandanplay;
+#[cfg(any(
+    feature = "ass",
+    feature = "bilibili",
+    feature = "cache",
+    feature = "dandanplay",
+    feature = "niconico",
+    feature = "serde",
+))]
+mod detect;
+#[cfg(feature = "bilibili")]
+mod encoding;
+#[cfg(feature = "serde")]
+pub mod jsonl;
+#[cfg(feature = "niconico")]
+pub mod niconico;
+#[cfg(feature = "subtitle")]
+pub mod subtitle;
+#[cfg(any(feature = "bilibili", feature = "niconico"))]
+mod xml;
+
+#[cfg(any(
+    feature = "ass",
+    feature = "bilibili",
+    feature = "cache",
+    feature = "dandanplay",
+    feature = "niconico",
+    feature = "serde",
+))]
+pub use detect::{
+    DetectError,
+    Format,
+    FormatError,
+    load_any,
+    sniff,
+};
@@ -0,0 +1,433 @@
+//! Niconico comment dumps, both the legacy XML (`<packet><chat .../></packet>`)
+//! and the JSON returned by the legacy and v1 comment APIs.
+
+use std::io::BufRead;
+
+use quick_xml::{
+    Reader,
+    events::{
+        BytesStart,
+        Event,
+    },
+};
+use serde_json::Value;
+use thiserror::Error;
+
+use super::xml::read_content;
+use crate::{
+    Color,
+    Danmaku,
+    DanmakuMeta,
+    DanmakuMode,
+    TrackDurations,
+};
+
+/// Niconico shows scrolling comments for 4 seconds and fixed ones for 3.
+pub const DURATIONS: TrackDurations = TrackDurations {
+    scroll: 4000.0,
+    center: 3000.0,
+};
+
+// Relative to the 24px "medium" size
+const BIG_SIZE: f32 = 39.0 / 24.0;
+const SMALL_SIZE: f32 = 15.0 / 24.0;
+
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("XML parsing error: {0}")]
+    Xml(#[from] quick_xml::Error),
+    #[error("Attribute parsing error: {0}")]
+    Attribute(#[from] quick_xml::events::attributes::AttrError),
+    #[error("JSON parsing error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unrecognised comment JSON layout")]
+    UnknownLayout,
+    #[error("Invalid value for '{field}': {value}")]
+    InvalidField { field: &'static str, value: String },
+}
+
+/// Style carried by the `mail` command field, e.g. `184 ue big red`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Commands {
+    pub mode: DanmakuMode,
+    pub color: Option<Color>,
+    pub size: Option<f32>,
+    // Neither is rendered, they only affect Niconico's line wrapping
+    pub ender: bool,
+    pub full: bool,
+    pub invisible: bool,
+}
+
+pub fn parse_commands(mail: &str) -> Commands {
+    let mut commands = Commands::default();
+
+    for command in mail.split_whitespace() {
+        match command {
+            "ue" => commands.mode = DanmakuMode::TopCenter,
+            "shita" => commands.mode = DanmakuMode::BottomCenter,
+            "naka" => commands.mode = DanmakuMode::Scroll,
+            "big" => commands.size = Some(BIG_SIZE),
+            "small" => commands.size = Some(SMALL_SIZE),
+            "medium" => commands.size = None,
+            "ender" => commands.ender = true,
+            "full" => commands.full = true,
+            "invisible" => commands.invisible = true,
+            _ => {
+                if let Some(color) = parse_color(command) {
+                    commands.color = Some(color);
+                }
+            }
+        }
+    }
+
+    commands
+}
+
+fn parse_color(command: &str) -> Option<Color> {
+    if let Some(hex) = command.strip_prefix('#') {
+        return (hex.len() == 6)
+            .then(|| u32::from_str_radix(hex, 16).ok())
+            .flatten()
+            .map(Color::from_rgb);
+    }
+
+    let rgb = match command {
+        "white" => 0xFFFFFF,
+        "red" => 0xFF0000,
+        "pink" => 0xFF8080,
+        "orange" => 0xFFC000,
+        "yellow" => 0xFFFF00,
+        "green" => 0x00FF00,
+        "cyan" => 0x00FFFF,
+        "blue" => 0x0000FF,
+        "purple" => 0xC000FF,
+        "black" => 0x000000,
+        // Premium only colors
+        "white2" | "niconicowhite" => 0xCCCC99,
+        "red2" | "truered" => 0xCC0033,
+        "pink2" => 0xFF33CC,
+        "orange2" | "passionorange" => 0xFF6600,
+        "yellow2" | "madyellow" => 0x999900,
+        "green2" | "elementalgreen" => 0x00CC66,
+        "cyan2" => 0x00CCCC,
+        "blue2" | "marineblue" => 0x3399FF,
+        "purple2" | "nobleviolet" => 0x6633CC,
+        "black2" => 0x666666,
+        _ => return None,
+    };
+
+    Some(Color::from_rgb(rgb))
+}
+
+struct Chat {
+    // milliseconds
+    start: f64,
+    content: String,
+    commands: Vec<String>,
+    no: Option<u64>,
+    user_id: Option<String>,
+    date: Option<i64>,
+    score: Option<i64>,
+    deleted: bool,
+}
+
+impl Chat {
+    fn into_danmaku(self) -> Option<Danmaku> {
+        let mail = self.commands.join(" ");
+        let commands = parse_commands(&mail);
+
+        if self.deleted || commands.invisible {
+            return None;
+        }
+
+        let mut meta = DanmakuMeta {
+            id: self.no,
+            sender: self.user_id,
+            send_time: self.date,
+            ..Default::default()
+        };
+        if !mail.is_empty() {
+            meta.extras.insert("mail".to_string(), mail);
+        }
+        if let Some(score) = self.score {
+            meta.extras.insert("score".to_string(), score.to_string());
+        }
+
+        Some(Danmaku {
+            content: self.content,
+            start: self.start,
+            color: commands.color.unwrap_or_default(),
+            mode: commands.mode,
+            size: commands.size,
+            meta,
+            ..Default::default()
+        })
+    }
+}
+
+pub fn parse_xml(xml: &str) -> Result<Vec<Danmaku>, ParseError> {
+    parse_xml_reader(xml.as_bytes())
+}
+
+pub fn parse_xml_reader<R: BufRead>(reader: R) -> Result<Vec<Danmaku>, ParseError> {
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    let mut danmaku = Vec::new();
+
+    loop {
+        let chat = match reader.read_event_into(&mut buf)? {
+            Event::Start(e) if e.name().as_ref() == b"chat" => {
+                let mut chat = chat_attributes(&e, &reader)?;
+                let name = e.name().as_ref().to_vec();
+                chat.content = read_content(&mut reader, &name, &mut buf)?;
+                chat
+            }
+            Event::Empty(e) if e.name().as_ref() == b"chat" => chat_attributes(&e, &reader)?,
+            Event::Eof => break,
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+
+        buf.clear();
+        danmaku.extend(chat.into_danmaku());
+    }
+
+    Ok(danmaku)
+}
+
+fn chat_attributes<R>(e: &BytesStart, reader: &Reader<R>) -> Result<Chat, ParseError> {
+    let mut chat = Chat {
+        start: 0.0,
+        content: String::new(),
+        commands: Vec::new(),
+        no: None,
+        user_id: None,
+        date: None,
+        score: None,
+        deleted: false,
+    };
+
+    for attr in e.attributes() {
+        let attr = attr?;
+        let value = attr.decode_and_unescape_value(reader.decoder())?;
+
+        match attr.key.as_ref() {
+            b"vpos" => chat.start = parse_field::<f64>("vpos", &value)? * 10.0,
+            b"no" => chat.no = Some(parse_field("no", &value)?),
+            b"date" => chat.date = Some(parse_field("date", &value)?),
+            b"score" => chat.score = Some(parse_field("score", &value)?),
+            b"user_id" => chat.user_id = Some(value.into_owned()),
+            b"mail" => chat.commands = value.split_whitespace().map(str::to_string).collect(),
+            b"deleted" => chat.deleted = value != "0",
+            _ => {}
+        }
+    }
+
+    Ok(chat)
+}
+
+fn parse_field<T: std::str::FromStr>(field: &'static str, value: &str) -> Result<T, ParseError> {
+    value.trim().parse().map_err(|_| ParseError::InvalidField {
+        field,
+        value: value.to_string(),
+    })
+}
+
+/// Accepts the legacy `[{"chat": {...}}, ...]` dumps and the v1
+/// `{"data": {"threads": [{"comments": [...]}]}}` responses.
+pub fn parse_json(json: &str) -> Result<Vec<Danmaku>, ParseError> {
+    let value: Value = serde_json::from_str(json)?;
+
+    let chats = if let Some(entries) = value.as_array() {
+        entries
+            .iter()
+            .filter_map(|entry| entry.get("chat"))
+            .map(legacy_chat)
+            .collect::<Result<Vec<_>, _>>()?
+    } else if let Some(threads) = value
+        .pointer("/data/threads")
+        .or_else(|| value.get("threads"))
+        .and_then(Value::as_array)
+    {
+        threads
+            .iter()
+            .filter_map(|thread| thread.get("comments").and_then(Value::as_array))
+            .flatten()
+            .map(v1_comment)
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        return Err(ParseError::UnknownLayout);
+    };
+
+    Ok(chats.into_iter().filter_map(Chat::into_danmaku).collect())
+}
+
+fn legacy_chat(chat: &Value) -> Result<Chat, ParseError> {
+    let vpos: f64 = required_json_field(chat, "vpos")?;
+
+    Ok(Chat {
+        start: vpos * 10.0,
+        content: chat
+            .get("content")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        commands: chat
+            .get("mail")
+            .and_then(Value::as_str)
+            .map(|mail| mail.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default(),
+        no: json_field(chat, "no")?,
+        user_id: chat.get("user_id").and_then(Value::as_str).map(str::to_string),
+        date: json_field(chat, "date")?,
+        score: json_field(chat, "score")?,
+        deleted: json_field::<u8>(chat, "deleted")?.is_some_and(|deleted| deleted != 0),
+    })
+}
+
+fn v1_comment(comment: &Value) -> Result<Chat, ParseError> {
+    Ok(Chat {
+        start: required_json_field(comment, "vposMs")?,
+        content: comment
+            .get("body")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string(),
+        commands: comment
+            .get("commands")
+            .and_then(Value::as_array)
+            .map(|commands| {
+                commands
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        no: json_field(comment, "no")?,
+        user_id: comment.get("userId").and_then(Value::as_str).map(str::to_string),
+        date: None,
+        score: json_field(comment, "score")?,
+        deleted: false,
+    })
+}
+
+// Numbers show up both as JSON numbers and as strings
+fn json_field<T: std::str::FromStr>(
+    object: &Value, field: &'static str,
+) -> Result<Option<T>, ParseError> {
+    match object.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => parse_field(field, value).map(Some),
+        Some(value) => parse_field(field, &value.to_string()).map(Some),
+    }
+}
+
+fn required_json_field<T: std::str::FromStr>(
+    object: &Value, field: &'static str,
+) -> Result<T, ParseError> {
+    json_field(object, field)?.ok_or_else(|| ParseError::InvalidField {
+        field,
+        value: "missing".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = include_str!("../../tests/fixtures/niconico/comments.xml");
+    const LEGACY_JSON: &str = include_str!("../../tests/fixtures/niconico/legacy.json");
+    const V1_JSON: &str = include_str!("../../tests/fixtures/niconico/v1.json");
+
+    #[test]
+    fn test_commands() {
+        let commands = parse_commands("184 ue big red ender");
+        assert_eq!(
+            commands,
+            Commands {
+                mode: DanmakuMode::TopCenter,
+                color: Some(Color::from_rgb(0xFF0000)),
+                size: Some(BIG_SIZE),
+                ender: true,
+                ..Default::default()
+            }
+        );
+
+        let commands = parse_commands("shita small #00ff80 full");
+        assert_eq!(commands.mode, DanmakuMode::BottomCenter);
+        assert_eq!(commands.color, Some(Color::from_rgb(0x00FF80)));
+        assert_eq!(commands.size, Some(SMALL_SIZE));
+        assert!(commands.full);
+
+        assert_eq!(parse_commands(""), Commands::default());
+        assert_eq!(parse_commands("#12345 blink").color, None);
+    }
+
+    #[test]
+    fn test_parse_xml() {
+        let danmaku = parse_xml(XML).unwrap();
+        assert_eq!(danmaku.len(), 3);
+
+        assert_eq!(danmaku[0].content, "初見");
+        assert_eq!(danmaku[0].start, 1230.0);
+        assert_eq!(danmaku[0].mode, DanmakuMode::Scroll);
+        assert_eq!(danmaku[0].color, Color::default());
+        assert_eq!(danmaku[0].meta.id, Some(1));
+        assert_eq!(danmaku[0].meta.sender.as_deref(), Some("abcdef"));
+        assert_eq!(danmaku[0].meta.send_time, Some(1300000000));
+
+        assert_eq!(danmaku[1].content, "うぽつ & わこつ");
+        assert_eq!(danmaku[1].mode, DanmakuMode::TopCenter);
+        assert_eq!(danmaku[1].color, Color::from_rgb(0xFF8080));
+        assert_eq!(danmaku[1].size, Some(BIG_SIZE));
+        assert_eq!(
+            danmaku[1].meta.extras.get("mail").map(String::as_str),
+            Some("184 ue big pink")
+        );
+
+        assert_eq!(danmaku[2].mode, DanmakuMode::BottomCenter);
+        assert_eq!(danmaku[2].color, Color::from_rgb(0x3399FF));
+    }
+
+    #[test]
+    fn test_parse_legacy_json() {
+        let danmaku = parse_json(LEGACY_JSON).unwrap();
+        assert_eq!(danmaku.len(), 2);
+
+        assert_eq!(danmaku[0].content, "wwwww");
+        assert_eq!(danmaku[0].start, 500.0);
+        assert_eq!(danmaku[1].mode, DanmakuMode::BottomCenter);
+        assert_eq!(danmaku[1].size, Some(SMALL_SIZE));
+        assert_eq!(danmaku[1].meta.id, Some(3));
+    }
+
+    #[test]
+    fn test_parse_v1_json() {
+        let danmaku = parse_json(V1_JSON).unwrap();
+        assert_eq!(danmaku.len(), 3);
+
+        assert_eq!(danmaku[0].content, "きたああああ");
+        assert_eq!(danmaku[0].start, 12340.0);
+        assert_eq!(danmaku[0].mode, DanmakuMode::TopCenter);
+        assert_eq!(danmaku[0].color, Color::from_rgb(0xFFFF00));
+        assert_eq!(danmaku[0].meta.sender.as_deref(), Some("nvc:user1"));
+        assert_eq!(
+            danmaku[0].meta.extras.get("score").map(String::as_str),
+            Some("-1000")
+        );
+
+        // Threads are concatenated, easy comments included
+        assert_eq!(danmaku[2].content, "8888");
+    }
+
+    #[test]
+    fn test_unknown_json_layout() {
+        assert!(matches!(
+            parse_json(r#"{"foo": 1}"#),
+            Err(ParseError::UnknownLayout)
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+//! SRT and WebVTT export, so comments can be followed with a screen reader
+//! or searched as plain subtitles. Comments are timed with the same on-screen
+//! durations the renderer uses.
+
+use std::{
+    io::{
+        self,
+        Write,
+    },
+    ops::Range,
+};
+
+use crate::{
+    Danmaku,
+    DanmakuMode,
+    TrackDurations,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CueFormat {
+    Srt,
+    WebVtt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Grouping {
+    /// One cue per comment for as long as it is on screen, cues may overlap.
+    PerComment,
+    /// Comments that are on screen at the same time share a cue.
+    Overlapping,
+    /// One cue per bucket of this many milliseconds, with every comment that
+    /// starts in it.
+    Buckets(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueOptions {
+    pub grouping: Grouping,
+    pub durations: TrackDurations,
+    /// Only comments starting in this range are exported, in milliseconds.
+    pub window: Option<Range<f64>>,
+}
+
+impl Default for CueOptions {
+    fn default() -> Self {
+        Self {
+            grouping: Grouping::Overlapping,
+            durations: TrackDurations::default(),
+            window: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    /// One line per comment, in start order.
+    pub lines: Vec<String>,
+}
+
+pub fn write<W: Write>(
+    mut writer: W, danmaku: &[Danmaku], format: CueFormat, options: &CueOptions,
+) -> io::Result<()> {
+    writer.write_all(to_string(danmaku, format, options).as_bytes())?;
+    writer.flush()
+}
+
+pub fn to_string(danmaku: &[Danmaku], format: CueFormat, options: &CueOptions) -> String {
+    let mut output = match format {
+        CueFormat::Srt => String::new(),
+        CueFormat::WebVtt => "WEBVTT\n\n".to_string(),
+    };
+
+    for (index, cue) in cues(danmaku, options).iter().enumerate() {
+        match format {
+            CueFormat::Srt => {
+                output.push_str(&format!(
+                    "{}\n{} --> {}\n",
+                    index + 1,
+                    timestamp(cue.start, ','),
+                    timestamp(cue.end, ',')
+                ));
+                for line in &cue.lines {
+                    output.push_str(line);
+                    output.push('\n');
+                }
+            }
+            CueFormat::WebVtt => {
+                output.push_str(&format!(
+                    "{} --> {}\n",
+                    timestamp(cue.start, '.'),
+                    timestamp(cue.end, '.')
+                ));
+                for line in &cue.lines {
+                    output.push_str(&escape_vtt(line));
+                    output.push('\n');
+                }
+            }
+        }
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Groups the track into cues, sorted by start time.
+pub fn cues(danmaku: &[Danmaku], options: &CueOptions) -> Vec<Cue> {
+    let mut sorted: Vec<&Danmaku> = danmaku
+        .iter()
+        .filter(|danmaku| {
+            options
+                .window
+                .as_ref()
+                .is_none_or(|window| window.contains(&danmaku.start))
+        })
+        .filter(|danmaku| !danmaku.content.trim().is_empty())
+        .collect();
+    sorted.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+    let mut cues: Vec<Cue> = Vec::new();
+    for danmaku in sorted {
+        let start = danmaku.start;
+        let end = start + on_screen_duration(danmaku, &options.durations);
+        let line = single_line(&danmaku.content);
+
+        match (options.grouping, cues.last_mut()) {
+            (Grouping::Overlapping, Some(cue)) if start < cue.end => {
+                cue.end = cue.end.max(end);
+                cue.lines.push(line);
+            }
+            (Grouping::Buckets(_), Some(cue)) if start < cue.end => cue.lines.push(line),
+            (Grouping::Buckets(size), _) => {
+                let size = size.max(1.0);
+                let bucket = (start / size).floor() * size;
+                cues.push(Cue {
+                    start: bucket,
+                    end: bucket + size,
+                    lines: vec![line],
+                });
+            }
+            _ => cues.push(Cue {
+                start,
+                end,
+                lines: vec![line],
+            }),
+        }
+    }
+
+    cues
+}
+
+fn on_screen_duration(danmaku: &Danmaku, durations: &TrackDurations) -> f64 {
+    match &danmaku.mode {
+        DanmakuMode::Scroll | DanmakuMode::ReverseScroll => durations.scroll as f64,
+        DanmakuMode::TopCenter | DanmakuMode::BottomCenter => durations.center as f64,
+        DanmakuMode::Positioned(spec) => spec.duration as f64,
+    }
+}
+
+// A blank line would end the cue early
+fn single_line(content: &str) -> String {
+    content.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn escape_vtt(line: &str) -> String {
+    line.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// SRT separates the milliseconds with a comma, WebVTT with a dot
+fn timestamp(milliseconds: f64, separator: char) -> String {
+    let milliseconds = milliseconds.max(0.0).round() as u64;
+
+    format!(
+        "{:02}:{:02}:{:02}{separator}{:03}",
+        milliseconds / 3_600_000,
+        milliseconds / 60_000 % 60,
+        milliseconds / 1000 % 60,
+        milliseconds % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> Vec<Danmaku> {
+        vec![
+            Danmaku::at("first", 1000.0).with_mode(DanmakuMode::Scroll),
+            Danmaku::at("top", 2000.0).with_mode(DanmakuMode::TopCenter),
+            Danmaku::at("much\n\nlater", 20000.0).with_mode(DanmakuMode::Scroll),
+            Danmaku::at("  ", 21000.0).with_mode(DanmakuMode::Scroll),
+        ]
+    }
+
+    #[test]
+    fn test_per_comment_srt() {
+        let options = CueOptions {
+            grouping: Grouping::PerComment,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            to_string(&track(), CueFormat::Srt, &options),
+            "1\n00:00:01,000 --> 00:00:09,000\nfirst\n\n\
+             2\n00:00:02,000 --> 00:00:07,000\ntop\n\n\
+             3\n00:00:20,000 --> 00:00:28,000\nmuch later\n\n"
+        );
+    }
+
+    #[test]
+    fn test_overlapping_webvtt() {
+        let mut track = track();
+        track[1].content = "<b> & </b>".to_string();
+
+        assert_eq!(
+            to_string(&track, CueFormat::WebVtt, &CueOptions::default()),
+            "WEBVTT\n\n\
+             00:00:01.000 --> 00:00:09.000\nfirst\n&lt;b&gt; &amp; &lt;/b&gt;\n\n\
+             00:00:20.000 --> 00:00:28.000\nmuch later\n\n"
+        );
+    }
+
+    #[test]
+    fn test_buckets_and_window() {
+        let options = CueOptions {
+            grouping: Grouping::Buckets(10000.0),
+            durations: TrackDurations {
+                scroll: 4000.0,
+                center: 3000.0,
+            },
+            window: Some(0.0..15000.0),
+        };
+
+        assert_eq!(
+            cues(&track(), &options),
+            [Cue {
+                start: 0.0,
+                end: 10000.0,
+                lines: vec!["first".to_string(), "top".to_string()],
+            }]
+        );
+    }
+}
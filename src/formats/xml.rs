@@ -0,0 +1,116 @@
+use std::io::BufRead;
+
+use quick_xml::{
+    Reader,
+    errors::IllFormedError,
+    events::Event,
+};
+
+// Collects the text of an element whose start tag was just read,
+// leaving the reader after its end tag.
+pub(crate) fn read_content<R: BufRead>(
+    reader: &mut Reader<R>, name: &[u8], buf: &mut Vec<u8>,
+) -> Result<String, quick_xml::Error> {
+    read_text(reader, name, buf, None)
+}
+
+// Like `read_content`, but broken escapes and characters XML doesn't allow are
+// dropped instead of failing. The flag tells whether anything was changed.
+#[cfg(feature = "bilibili")]
+pub(crate) fn read_repaired_content<R: BufRead>(
+    reader: &mut Reader<R>, name: &[u8], buf: &mut Vec<u8>,
+) -> Result<(String, bool), quick_xml::Error> {
+    let mut repaired = false;
+    let mut content = read_text(reader, name, buf, Some(&mut repaired))?;
+
+    let len = content.len();
+    content.retain(is_xml_char);
+    repaired |= content.len() != len;
+
+    Ok((content, repaired))
+}
+
+fn read_text<R: BufRead>(
+    reader: &mut Reader<R>, name: &[u8], buf: &mut Vec<u8>, mut repaired: Option<&mut bool>,
+) -> Result<String, quick_xml::Error> {
+    let mut content = String::new();
+    let mut depth = 0usize;
+
+    loop {
+        buf.clear();
+        match reader.read_event_into(buf)? {
+            Event::Text(text) => match (text.unescape(), repaired.as_deref_mut()) {
+                (Ok(text), _) => content.push_str(&text),
+                (Err(_), Some(repaired)) => {
+                    let raw = reader.decoder().decode(&text)?;
+                    content.push_str(&lenient_unescape(&raw));
+                    *repaired = true;
+                }
+                (Err(e), None) => return Err(e),
+            },
+            Event::CData(data) => content.push_str(&reader.decoder().decode(&data)?),
+            Event::Start(_) => depth += 1,
+            Event::End(end) if depth == 0 && end.name().as_ref() == name => break,
+            Event::End(_) => depth = depth.saturating_sub(1),
+            Event::Eof => {
+                return Err(quick_xml::Error::IllFormed(IllFormedError::MissingEndTag(
+                    String::from_utf8_lossy(name).into_owned(),
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(content)
+}
+
+// Resolves what it can, drops invalid character references and keeps unknown
+// entities as written
+fn lenient_unescape(raw: &str) -> String {
+    let mut unescaped = String::with_capacity(raw.len());
+    let mut rest = raw;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+
+        let entity = &rest[1..end];
+        let resolved = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "apos" => Some('\''),
+            "quot" => Some('"'),
+            _ => entity.strip_prefix('#').and_then(char_reference),
+        };
+
+        match resolved {
+            Some(c) => unescaped.push(c),
+            None if entity.starts_with('#') => {}
+            None => unescaped.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+fn char_reference(reference: &str) -> Option<char> {
+    let code = match reference.strip_prefix('x') {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => reference.parse(),
+    };
+
+    code.ok().and_then(char::from_u32)
+}
+
+// https://www.w3.org/TR/xml/#charsets
+#[cfg(feature = "bilibili")]
+fn is_xml_char(c: char) -> bool {
+    !matches!(c, '\0'..='\u{8}' | '\u{B}' | '\u{C}' | '\u{E}'..='\u{1F}' | '\u{FFFE}' | '\u{FFFF}')
+}
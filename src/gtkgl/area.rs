@@ -4,7 +4,10 @@ use gtk::{
     prelude::*,
     subclass::prelude::*,
 };
-use std::cell::RefCell;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+};
 
 mod imp {
     use std::panic;
@@ -41,6 +44,14 @@ mod imp {
         pub enable_danmaku: RefCell<bool>,
 
         pub clock: RefCell<Option<DanmakuClock>>,
+        // Code to width, height and RGBA
+        pub emotes: RefCell<HashMap<String, (u32, u32, Vec<u8>)>>,
+        pub durations: RefCell<crate::TrackDurations>,
+        pub density: RefCell<Option<crate::DensityLimiter>>,
+        pub repeats: RefCell<Option<crate::RepeatMerger>>,
+        // Held while there is no renderer, so it survives unrealize
+        pub live: RefCell<Option<crate::LiveSource>>,
+        pub filters: RefCell<crate::FilterSet>,
 
         pub renderer: RefCell<Option<DanmakwAreaRenderer>>,
         render_loop_callback_id: RefCell<Option<TickCallbackId>>,
@@ -60,6 +71,12 @@ mod imp {
                 top_center_max_lines: RefCell::new(5),
                 enable_danmaku: RefCell::new(true),
                 clock: RefCell::new(None),
+                emotes: RefCell::new(HashMap::new()),
+                durations: RefCell::new(Default::default()),
+                density: RefCell::new(None),
+                repeats: RefCell::new(None),
+                live: RefCell::new(None),
+                filters: RefCell::new(crate::FilterSet::new()),
                 renderer: RefCell::new(None),
                 render_loop_callback_id: RefCell::new(None),
             }
@@ -113,12 +130,38 @@ mod imp {
 
             let mut renderer = DanmakwAreaRenderer::new();
             renderer.danmaku_renderer.set_font_name(self.font_name());
+            renderer
+                .danmaku_renderer
+                .set_durations(*self.durations.borrow());
+            renderer
+                .danmaku_renderer
+                .set_density_limit(self.density.borrow().clone());
+            renderer
+                .danmaku_renderer
+                .set_repeat_merge(self.repeats.borrow().clone());
+            // Checked when they were stored, so registering again can't fail
+            for (code, (width, height, rgba)) in self.emotes.borrow().iter() {
+                let _ = renderer.danmaku_renderer.register_emote(
+                    code.as_str(),
+                    *width,
+                    *height,
+                    rgba.clone(),
+                );
+            }
+            renderer.danmaku_renderer.set_filters(self.filters.take());
+            if let Some(live) = self.live.take() {
+                renderer.danmaku_renderer.attach_live(live);
+            }
             self.renderer.replace(Some(renderer));
         }
 
         fn unrealize(&self) {
             self.obj().pause();
-            self.renderer.replace(None);
+            if let Some(mut renderer) = self.renderer.take() {
+                self.live.replace(renderer.danmaku_renderer.detach_live());
+                self.filters
+                    .replace(renderer.danmaku_renderer.take_filters());
+            }
             self.parent_unrealize();
         }
     }
@@ -355,6 +398,143 @@ impl DanmakwArea {
         }
     }
 
+    // Keeps the playback position, unlike `set_danmaku`
+    pub fn replace_danmaku(&self, danmaku: Vec<crate::Danmaku>) {
+        if let Some(renderer) = self.imp().renderer.borrow_mut().as_mut() {
+            renderer.danmaku_renderer.replace_danmaku(danmaku);
+        }
+    }
+
+    // Unlike `Renderer::add_text` the comment stays in the track, so it shows up
+    // again after seeking back
+    pub fn insert_danmaku(&self, danmaku: crate::Danmaku) {
+        if let Some(renderer) = self.imp().renderer.borrow_mut().as_mut() {
+            renderer.danmaku_renderer.insert_danmaku(danmaku);
+        }
+    }
+
+    // Retracts every comment with this id, including ones on screen
+    pub fn remove_danmaku(&self, id: u64) -> usize {
+        self.imp()
+            .renderer
+            .borrow_mut()
+            .as_mut()
+            .map_or(0, |renderer| renderer.danmaku_renderer.remove_danmaku(id))
+    }
+
+    // Plays a track while it is still being parsed
+    pub fn load_danmaku(&self, loader: crate::TrackLoader) {
+        if let Some(renderer) = self.imp().renderer.borrow_mut().as_mut() {
+            renderer.danmaku_renderer.load(loader);
+        }
+    }
+
+    // Comments pushed through the source's senders show up on the next frame
+    pub fn attach_live(&self, source: crate::LiveSource) {
+        match self.imp().renderer.borrow_mut().as_mut() {
+            Some(renderer) => renderer.danmaku_renderer.attach_live(source),
+            None => {
+                self.imp().live.replace(Some(source));
+            }
+        }
+    }
+
+    pub fn detach_live(&self) -> Option<crate::LiveSource> {
+        match self.imp().renderer.borrow_mut().as_mut() {
+            Some(renderer) => renderer.danmaku_renderer.detach_live(),
+            None => self.imp().live.take(),
+        }
+    }
+
+    // Hides comments the filters block, including ones already on screen
+    pub fn set_filters(&self, filters: crate::FilterSet) {
+        match self.imp().renderer.borrow_mut().as_mut() {
+            Some(renderer) => renderer.danmaku_renderer.set_filters(filters),
+            None => {
+                self.imp().filters.replace(filters);
+            }
+        }
+    }
+
+    // Also reads the counters, e.g. `area.edit_filters(|f| f.total_removed())`
+    pub fn edit_filters<T>(&self, edit: impl FnOnce(&mut crate::FilterSet) -> T) -> T {
+        match self.imp().renderer.borrow_mut().as_mut() {
+            Some(renderer) => renderer.danmaku_renderer.edit_filters(edit),
+            None => edit(&mut self.imp().filters.borrow_mut()),
+        }
+    }
+
+    pub fn visible_danmaku(&self) -> Vec<crate::Danmaku> {
+        self.imp()
+            .renderer
+            .borrow()
+            .as_ref()
+            .map(|renderer| {
+                renderer
+                    .danmaku_renderer
+                    .visible_danmaku()
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn register_emote(
+        &self, code: &str, width: u32, height: u32, rgba: Vec<u8>,
+    ) -> Result<(), crate::EmoteError> {
+        match self.imp().renderer.borrow_mut().as_mut() {
+            Some(renderer) => {
+                renderer
+                    .danmaku_renderer
+                    .register_emote(code, width, height, rgba.clone())?
+            }
+            None => crate::renderer::check_emote(code, width, height, &rgba)?,
+        }
+
+        // Kept around so the emote survives the renderer being recreated on realize
+        self.imp()
+            .emotes
+            .borrow_mut()
+            .insert(code.to_string(), (width, height, rgba));
+
+        Ok(())
+    }
+
+    pub fn unregister_emote(&self, code: &str) {
+        self.imp().emotes.borrow_mut().remove(code);
+        if let Some(renderer) = self.imp().renderer.borrow_mut().as_mut() {
+            renderer.danmaku_renderer.unregister_emote(code);
+        }
+    }
+
+    pub fn clear_emotes(&self) {
+        self.imp().emotes.borrow_mut().clear();
+        if let Some(renderer) = self.imp().renderer.borrow_mut().as_mut() {
+            renderer.danmaku_renderer.clear_emotes();
+        }
+    }
+
+    pub fn set_durations(&self, durations: crate::TrackDurations) {
+        self.imp().durations.replace(durations);
+        if let Some(renderer) = self.imp().renderer.borrow_mut().as_mut() {
+            renderer.danmaku_renderer.set_durations(durations);
+        }
+    }
+
+    pub fn set_repeat_merge(&self, merger: Option<crate::RepeatMerger>) {
+        self.imp().repeats.replace(merger.clone());
+        if let Some(renderer) = self.imp().renderer.borrow_mut().as_mut() {
+            renderer.danmaku_renderer.set_repeat_merge(merger);
+        }
+    }
+
+    pub fn set_density_limit(&self, limiter: Option<crate::DensityLimiter>) {
+        self.imp().density.replace(limiter.clone());
+        if let Some(renderer) = self.imp().renderer.borrow_mut().as_mut() {
+            renderer.danmaku_renderer.set_density_limit(limiter);
+        }
+    }
+
     pub fn clear_danmaku(&self) {
         if let Some(renderer) = self.imp().renderer.borrow_mut().as_mut() {
             renderer.danmaku_renderer.clear();
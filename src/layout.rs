@@ -0,0 +1,479 @@
+// Row allocation shared by the renderer and the subtitle exporters, so an
+// exported track is laid out the same way it plays.
+
+use std::iter;
+
+use glyphon::{
+    Attrs,
+    Buffer,
+    Family,
+    FontSystem,
+    Metrics,
+    Shaping,
+};
+
+use crate::{
+    Danmaku,
+    DanmakuMode,
+    TrackDurations,
+};
+
+// A scrolling danmaku already on screen, as seen by the row allocator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ScrollLane {
+    pub row: usize,
+    pub rows: usize,
+    // How far the trailing edge still has to travel to leave the screen
+    pub distance: f32,
+    // Text width in pixels
+    pub width: f32,
+    // Pixels per millisecond
+    pub speed: f32,
+}
+
+// A row can take the danmaku once every danmaku overlapping the rows it
+// spans has fully entered the screen and will leave before it catches up
+pub(crate) fn find_scroll_rows<I>(
+    lanes: I, max_rows: usize, rows: usize, width: f32, speed: f32, spacing: f32,
+) -> Option<usize>
+where
+    I: Iterator<Item = ScrollLane> + Clone,
+{
+    (0..(max_rows + 1).saturating_sub(rows)).find(|&row| {
+        lanes
+            .clone()
+            .filter(|lane| lane.row < row + rows && row < lane.row + lane.rows)
+            .all(|lane| keeps_clear(&lane, width, speed, spacing))
+    })
+}
+
+// Rows for a scrolling danmaku on screen whose text changed, e.g. its repeat
+// count went up. `lane` has its new size and `lanes` are the others. It stays
+// in its rows if it still fits there.
+pub(crate) fn refit_scroll_rows<I>(
+    lanes: I, max_rows: usize, lane: ScrollLane, spacing: f32,
+) -> Option<usize>
+where
+    I: Iterator<Item = ScrollLane> + Clone,
+{
+    let lead = lane.distance - lane.width;
+    let fits = |row: usize| {
+        lanes
+            .clone()
+            .filter(|other| other.row < row + lane.rows && row < other.row + other.rows)
+            .all(|other| {
+                keeps_clear(&other, lead, lane.speed, spacing)
+                    || keeps_clear(&lane, other.distance - other.width, other.speed, spacing)
+            })
+    };
+
+    iter::once(lane.row)
+        .chain(0..(max_rows + 1).saturating_sub(lane.rows))
+        .find(|&row| row + lane.rows <= max_rows && fits(row))
+}
+
+// Whether a danmaku whose leading edge still has `lead` to travel stays
+// `spacing` behind `ahead` now and until `ahead` has left the screen
+fn keeps_clear(ahead: &ScrollLane, lead: f32, speed: f32, spacing: f32) -> bool {
+    let leave_time = (ahead.distance + spacing) / ahead.speed;
+
+    leave_time < lead / speed && lead > ahead.distance + spacing
+}
+
+pub(crate) fn find_free_rows(occupied: &[bool], rows: usize) -> Option<usize> {
+    (0..(occupied.len() + 1).saturating_sub(rows))
+        .find(|&row| occupied[row..row + rows].iter().all(|&occupied| !occupied))
+}
+
+// Like `refit_scroll_rows` for centered danmaku, whose own rows have to be
+// freed first
+pub(crate) fn refit_free_rows(occupied: &[bool], row: usize, rows: usize) -> Option<usize> {
+    occupied
+        .get(row..row + rows)
+        .filter(|range| range.iter().all(|&occupied| !occupied))
+        .map(|_| row)
+        .or_else(|| find_free_rows(occupied, rows))
+}
+
+/// Measures the rendered width of danmaku text in pixels.
+pub trait TextMeasure {
+    fn measure(&mut self, text: &str, font_size: f32) -> f32;
+}
+
+/// Shapes text with the same fonts the renderer uses.
+pub struct FontMeasure {
+    font_system: FontSystem,
+    font_name: String,
+}
+
+impl FontMeasure {
+    pub fn new(font_name: impl Into<String>) -> Self {
+        Self {
+            font_system: FontSystem::new(),
+            font_name: font_name.into(),
+        }
+    }
+}
+
+impl TextMeasure for FontMeasure {
+    fn measure(&mut self, text: &str, font_size: f32) -> f32 {
+        let mut buffer = Buffer::new(&mut self.font_system, Metrics::new(font_size, font_size));
+        let attrs = Attrs::new().family(Family::Name(&self.font_name));
+        buffer.set_text(&mut self.font_system, text, &attrs, Shaping::Advanced);
+
+        buffer
+            .layout_runs()
+            .map(|run| run.line_w)
+            .reduce(f32::max)
+            .unwrap_or(0.0)
+    }
+}
+
+/// Every character is `self.0` times the font size wide, handy for tests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedWidth(pub f32);
+
+impl TextMeasure for FixedWidth {
+    fn measure(&mut self, text: &str, font_size: f32) -> f32 {
+        text.chars().count() as f32 * font_size * self.0
+    }
+}
+
+/// Screen and track settings, the defaults match a fresh renderer at a
+/// scale factor of 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutOptions {
+    pub width: f32,
+    pub height: f32,
+    pub font_name: String,
+    pub font_size: f32,
+    pub line_height: f32,
+    pub top_padding: f32,
+    // Minimum gap between scrolling danmaku in the same row
+    pub spacing: f32,
+    pub scroll_max_rows: usize,
+    pub top_center_max_rows: usize,
+    pub bottom_center_max_rows: usize,
+    pub durations: TrackDurations,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        Self {
+            width: 1920.0,
+            height: 1080.0,
+            font_name: String::new(),
+            font_size: 28.0,
+            line_height: 28.0 * 1.4,
+            top_padding: 10.0,
+            spacing: 20.0,
+            scroll_max_rows: 20,
+            top_center_max_rows: 10,
+            bottom_center_max_rows: 10,
+            durations: TrackDurations::default(),
+        }
+    }
+}
+
+impl LayoutOptions {
+    // Top edge of a row counted from the top of the screen
+    pub fn row_top(&self, row: usize) -> f32 {
+        self.top_padding + row as f32 * self.line_height
+    }
+
+    // Top edge of a row range counted from the bottom of the screen
+    pub fn bottom_row_top(&self, row: usize, rows: usize) -> f32 {
+        self.height - self.top_padding - (row + rows) as f32 * self.line_height
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    Scroll { row: usize, rows: usize },
+    ReverseScroll { row: usize, rows: usize },
+    TopCenter { row: usize, rows: usize },
+    BottomCenter { row: usize, rows: usize },
+    Positioned,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlacedDanmaku<'a> {
+    pub danmaku: &'a Danmaku,
+    // milliseconds
+    pub start: f64,
+    pub end: f64,
+    // Text width in pixels
+    pub width: f32,
+    pub placement: Placement,
+}
+
+struct ActiveScroll {
+    row: usize,
+    rows: usize,
+    start: f64,
+    distance: f32,
+    width: f32,
+    speed: f32,
+}
+
+impl ActiveScroll {
+    fn lane_at(&self, time: f64) -> ScrollLane {
+        ScrollLane {
+            row: self.row,
+            rows: self.rows,
+            distance: self.distance - self.speed * (time - self.start) as f32,
+            width: self.width,
+            speed: self.speed,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ScrollTrack {
+    active: Vec<ActiveScroll>,
+}
+
+impl ScrollTrack {
+    fn place(
+        &mut self, options: &LayoutOptions, time: f64, rows: usize, text_width: f32,
+    ) -> Option<usize> {
+        self.active.retain(|scroll| scroll.lane_at(time).distance > 0.0);
+
+        let distance = options.width + text_width;
+        let speed = distance / options.durations.scroll;
+        let lanes = self.active.iter().map(|scroll| scroll.lane_at(time));
+        let row = find_scroll_rows(
+            lanes,
+            options.scroll_max_rows,
+            rows,
+            options.width,
+            speed,
+            options.spacing,
+        )?;
+
+        self.active.push(ActiveScroll {
+            row,
+            rows,
+            start: time,
+            distance,
+            width: text_width,
+            speed,
+        });
+
+        Some(row)
+    }
+}
+
+struct CenterTrack {
+    // When each row frees up
+    row_ends: Vec<f64>,
+}
+
+impl CenterTrack {
+    fn new(max_rows: usize) -> Self {
+        Self {
+            row_ends: vec![f64::NEG_INFINITY; max_rows],
+        }
+    }
+
+    fn place(&mut self, time: f64, end: f64, rows: usize) -> Option<usize> {
+        let occupied: Vec<bool> = self.row_ends.iter().map(|&row_end| row_end > time).collect();
+        let row = find_free_rows(&occupied, rows)?;
+
+        self.row_ends[row..row + rows].fill(end);
+        Some(row)
+    }
+}
+
+/// Lays out a whole track the way the renderer would play it. Danmaku that
+/// would not fit on screen are left out, like in the renderer.
+pub fn layout_track<'a>(
+    danmaku: &'a [Danmaku], options: &LayoutOptions, measure: &mut impl TextMeasure,
+) -> Vec<PlacedDanmaku<'a>> {
+    let mut sorted: Vec<&Danmaku> = danmaku.iter().collect();
+    sorted.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+    let mut scroll = ScrollTrack::default();
+    let mut reverse_scroll = ScrollTrack::default();
+    let mut top_center = CenterTrack::new(options.top_center_max_rows);
+    let mut bottom_center = CenterTrack::new(options.bottom_center_max_rows);
+
+    let mut placed = Vec::with_capacity(sorted.len());
+    for danmaku in sorted {
+        let start = danmaku.start;
+        let rows = danmaku.row_span();
+        let width = measure.measure(&danmaku.content, options.font_size * danmaku.scale());
+        let scroll_end = start + options.durations.scroll as f64;
+        let center_end = start + options.durations.center as f64;
+
+        let (placement, end) = match &danmaku.mode {
+            DanmakuMode::Scroll => match scroll.place(options, start, rows, width) {
+                Some(row) => (Placement::Scroll { row, rows }, scroll_end),
+                None => continue,
+            },
+            DanmakuMode::ReverseScroll => {
+                match reverse_scroll.place(options, start, rows, width) {
+                    Some(row) => (Placement::ReverseScroll { row, rows }, scroll_end),
+                    None => continue,
+                }
+            }
+            DanmakuMode::TopCenter => match top_center.place(start, center_end, rows) {
+                Some(row) => (Placement::TopCenter { row, rows }, center_end),
+                None => continue,
+            },
+            DanmakuMode::BottomCenter => match bottom_center.place(start, center_end, rows) {
+                Some(row) => (Placement::BottomCenter { row, rows }, center_end),
+                None => continue,
+            },
+            DanmakuMode::Positioned(spec) => (Placement::Positioned, start + spec.duration as f64),
+        };
+
+        placed.push(PlacedDanmaku {
+            danmaku,
+            start,
+            end,
+            width,
+            placement,
+        });
+    }
+
+    placed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> LayoutOptions {
+        LayoutOptions {
+            width: 1000.0,
+            height: 500.0,
+            font_size: 20.0,
+            line_height: 25.0,
+            scroll_max_rows: 2,
+            top_center_max_rows: 2,
+            bottom_center_max_rows: 2,
+            ..Default::default()
+        }
+    }
+
+    fn placements(track: &[Danmaku]) -> Vec<(&str, Placement)> {
+        layout_track(track, &options(), &mut FixedWidth(1.0))
+            .into_iter()
+            .map(|placed| (placed.danmaku.content.as_str(), placed.placement))
+            .collect()
+    }
+
+    #[test]
+    fn test_scroll_rows() {
+        let track = [
+            Danmaku::at("aaaaa", 0.0).with_mode(DanmakuMode::Scroll),
+            Danmaku::at("bbbbb", 0.0).with_mode(DanmakuMode::Scroll),
+            // Both rows are still taken
+            Danmaku::at("ccccc", 100.0).with_mode(DanmakuMode::Scroll),
+            // The first one has moved far enough by now
+            Danmaku::at("ddddd", 2000.0).with_mode(DanmakuMode::Scroll),
+        ];
+
+        assert_eq!(
+            placements(&track),
+            vec![
+                ("aaaaa", Placement::Scroll { row: 0, rows: 1 }),
+                ("bbbbb", Placement::Scroll { row: 1, rows: 1 }),
+                ("ddddd", Placement::Scroll { row: 0, rows: 1 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_slow_danmaku_is_not_caught_up() {
+        // Long text moves faster, so it may not follow short text too soon
+        let track = [
+            Danmaku::at("a", 0.0).with_mode(DanmakuMode::Scroll),
+            Danmaku::at(&"b".repeat(40), 1000.0).with_mode(DanmakuMode::Scroll),
+        ];
+
+        assert_eq!(placements(&track)[1].1, Placement::Scroll { row: 1, rows: 1 });
+    }
+
+    #[test]
+    fn test_center_rows_expire() {
+        let track = [
+            Danmaku::at("a", 0.0).with_mode(DanmakuMode::TopCenter),
+            Danmaku::at("b", 100.0).with_mode(DanmakuMode::TopCenter),
+            Danmaku::at("c", 200.0).with_mode(DanmakuMode::TopCenter),
+            Danmaku::at("d", 5000.0).with_mode(DanmakuMode::TopCenter),
+            Danmaku::at("e", 0.0).with_mode(DanmakuMode::BottomCenter),
+        ];
+
+        assert_eq!(
+            placements(&track),
+            vec![
+                ("a", Placement::TopCenter { row: 0, rows: 1 }),
+                ("e", Placement::BottomCenter { row: 0, rows: 1 }),
+                ("b", Placement::TopCenter { row: 1, rows: 1 }),
+                ("d", Placement::TopCenter { row: 0, rows: 1 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_large_danmaku_spans_rows() {
+        let mut big = Danmaku::at("big", 0.0).with_mode(DanmakuMode::ReverseScroll);
+        big.size = Some(1.5);
+        let track = [
+            big,
+            Danmaku::at("small", 0.0).with_mode(DanmakuMode::ReverseScroll),
+        ];
+
+        assert_eq!(
+            placements(&track),
+            vec![("big", Placement::ReverseScroll { row: 0, rows: 2 })]
+        );
+    }
+
+    #[test]
+    fn test_find_free_rows() {
+        assert_eq!(find_free_rows(&[true, false, false], 2), Some(1));
+        assert_eq!(find_free_rows(&[false, true, false], 2), None);
+        assert_eq!(find_free_rows(&[], 1), None);
+    }
+
+    #[test]
+    fn test_refit_scroll_rows() {
+        // Moving left at 0.1px/ms, `x` is the leading edge
+        let lane = |row, rows, x: f32, width: f32| ScrollLane {
+            row,
+            rows,
+            distance: x + width,
+            width,
+            speed: 0.1,
+        };
+        let refit = |lanes: &[ScrollLane], max_rows, lane| {
+            refit_scroll_rows(lanes.iter().copied(), max_rows, lane, 20.0)
+        };
+        // Its ×N made it 300px wide instead of 100px
+        let grown = lane(0, 1, 100.0, 300.0);
+        let ahead = lane(0, 1, 0.0, 50.0);
+
+        assert_eq!(refit(&[ahead, lane(0, 1, 500.0, 100.0)], 2, grown), Some(0));
+
+        // The one behind would now overlap it
+        let behind = [ahead, lane(0, 1, 250.0, 100.0)];
+        assert_eq!(refit(&behind, 2, grown), Some(1));
+        assert_eq!(refit(&behind, 1, grown), None);
+
+        // Grown to two rows, the next one is taken
+        let taller = ScrollLane { rows: 2, ..grown };
+        let below = [lane(1, 1, 100.0, 100.0)];
+        assert_eq!(refit(&below, 4, taller), Some(2));
+        assert_eq!(refit(&below, 3, taller), None);
+    }
+
+    #[test]
+    fn test_refit_free_rows() {
+        assert_eq!(refit_free_rows(&[false, false, true], 0, 2), Some(0));
+        assert_eq!(refit_free_rows(&[false, true, false, false], 0, 2), Some(2));
+        assert_eq!(refit_free_rows(&[false, true, false], 2, 2), None);
+    }
+}
@@ -2,18 +2,69 @@ mod danmaku;
 mod renderer;
 mod gtkgl;
 mod clock;
+mod layout;
+mod live;
+mod loader;
+pub mod formats;
 
 pub use gtkgl::*;
 pub use danmaku::{
+    AlphaKeyframe,
+    CENTER_DURATION_MS,
     CenterDanmaku,
     Color,
+    ColorFilter,
     Danmaku,
+    DanmakuFilter,
+    DanmakuMeta,
     DanmakuMode,
     DanmakuQueue,
+    DensityLimiter,
+    FilterId,
+    FilterSet,
+    KeywordFilter,
+    LengthFilter,
+    ModeFilter,
+    MotionSpec,
+    ParseColorError,
+    PathPoint,
+    PositionedDanmaku,
+    RepeatMerger,
+    SCROLL_DURATION_MS,
     ScrollingDanmaku,
+    SenderFilter,
+    SourceId,
+    TextSpan,
+    TrackDurations,
+    TrackMerger,
+};
+#[cfg(feature = "regex")]
+pub use danmaku::RegexFilter;
+pub use renderer::{
+    EmoteError,
+    Renderer,
 };
-pub use renderer::Renderer;
 pub use clock::DanmakuClock;
+pub use live::{
+    DropPolicy,
+    LiveClosed,
+    LiveSender,
+    LiveSource,
+};
+pub use loader::{
+    LoadError,
+    LoadState,
+    TrackLoader,
+};
+pub use layout::{
+    FixedWidth,
+    FontMeasure,
+    LayoutOptions,
+    PlacedDanmaku,
+    Placement,
+    TextMeasure,
+    layout_track,
+};
 
 use gtk::prelude::*;
 
@@ -0,0 +1,206 @@
+use std::sync::{
+    Arc,
+    atomic::{
+        AtomicBool,
+        AtomicU64,
+        Ordering,
+    },
+};
+
+use thiserror::Error;
+
+use crate::Danmaku;
+
+// What a sender does when the renderer falls behind and the channel is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DropPolicy {
+    // Waits for room, slowing the producer down to the frame rate
+    Block,
+    // Discards the comment being sent
+    #[default]
+    DropNewest,
+    // Discards the oldest waiting comment, so what is shown stays current
+    DropOldest,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Live source was dropped")]
+pub struct LiveClosed;
+
+#[derive(Default)]
+struct Shared {
+    dropped: AtomicU64,
+    closed: AtomicBool,
+}
+
+// Comments of a live stream, pushed from any thread through a `LiveSender`.
+// The renderer drains it every frame and shows new comments right away,
+// their start time is ignored.
+pub struct LiveSource {
+    receiver: flume::Receiver<Danmaku>,
+    shared: Arc<Shared>,
+}
+
+#[derive(Clone)]
+pub struct LiveSender {
+    sender: flume::Sender<Danmaku>,
+    // Only kept for `DropOldest`, to make room in a full channel
+    oldest: Option<flume::Receiver<Danmaku>>,
+    policy: DropPolicy,
+    shared: Arc<Shared>,
+}
+
+impl LiveSource {
+    // At most `capacity` comments wait for the next frame
+    pub fn bounded(capacity: usize, policy: DropPolicy) -> (LiveSender, Self) {
+        let (sender, receiver) = flume::bounded(capacity.max(1));
+        let shared = Arc::new(Shared::default());
+
+        let sender = LiveSender {
+            sender,
+            oldest: (policy == DropPolicy::DropOldest).then(|| receiver.clone()),
+            policy,
+            shared: shared.clone(),
+        };
+
+        (sender, Self { receiver, shared })
+    }
+
+    // Takes every waiting comment without blocking
+    pub fn drain(&self) -> impl Iterator<Item = Danmaku> + '_ {
+        self.receiver.try_iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.receiver.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receiver.is_empty()
+    }
+
+    // Comments discarded by the drop policy so far
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    // Every sender is gone, nothing new will arrive after the waiting comments
+    pub fn is_disconnected(&self) -> bool {
+        self.receiver.is_disconnected()
+    }
+}
+
+impl Drop for LiveSource {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+impl LiveSender {
+    // Only fails once the `LiveSource` is dropped. A comment discarded by
+    // the drop policy still counts as sent, see `dropped`.
+    pub fn send(&self, danmaku: Danmaku) -> Result<(), LiveClosed> {
+        if self.is_closed() {
+            return Err(LiveClosed);
+        }
+
+        let mut danmaku = danmaku;
+        loop {
+            let rejected = match self.policy {
+                DropPolicy::Block => {
+                    return self.sender.send(danmaku).map_err(|_| LiveClosed);
+                }
+                _ => match self.sender.try_send(danmaku) {
+                    Ok(()) => return Ok(()),
+                    Err(flume::TrySendError::Full(rejected)) => rejected,
+                    Err(flume::TrySendError::Disconnected(_)) => return Err(LiveClosed),
+                },
+            };
+
+            let Some(oldest) = &self.oldest else {
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            };
+            // The renderer may have drained it in the meantime
+            if oldest.try_recv().is_ok() {
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            danmaku = rejected;
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn contents(source: &LiveSource) -> Vec<String> {
+        source.drain().map(|danmaku| danmaku.content).collect()
+    }
+
+    #[test]
+    fn test_threaded_producer() {
+        let (sender, source) = LiveSource::bounded(4, DropPolicy::Block);
+
+        let producer = thread::spawn(move || {
+            for i in 0..100 {
+                sender.send(Danmaku::at(&i.to_string(), 0.0)).unwrap();
+            }
+        });
+
+        let mut received = Vec::new();
+        while received.len() < 100 {
+            received.extend(contents(&source));
+            thread::yield_now();
+        }
+        producer.join().unwrap();
+
+        let expected: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        assert_eq!(received, expected);
+        assert_eq!(source.dropped(), 0);
+        assert!(source.is_disconnected());
+    }
+
+    #[test]
+    fn test_drop_policies() {
+        let (sender, source) = LiveSource::bounded(2, DropPolicy::DropNewest);
+        for content in ["a", "b", "c", "d"] {
+            sender.send(Danmaku::at(content, 0.0)).unwrap();
+        }
+        assert_eq!(contents(&source), ["a", "b"]);
+        assert_eq!(sender.dropped(), 2);
+
+        let (sender, source) = LiveSource::bounded(2, DropPolicy::DropOldest);
+        for content in ["a", "b", "c", "d"] {
+            sender.send(Danmaku::at(content, 0.0)).unwrap();
+        }
+        assert_eq!(source.len(), 2);
+        assert_eq!(contents(&source), ["c", "d"]);
+        assert_eq!(source.dropped(), 2);
+    }
+
+    #[test]
+    fn test_closed() {
+        for policy in [
+            DropPolicy::Block,
+            DropPolicy::DropNewest,
+            DropPolicy::DropOldest,
+        ] {
+            let (sender, source) = LiveSource::bounded(1, policy);
+            drop(source);
+
+            assert!(sender.is_closed());
+            assert_eq!(sender.send(Danmaku::at("a", 0.0)), Err(LiveClosed));
+        }
+    }
+}
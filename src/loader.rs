@@ -0,0 +1,136 @@
+use std::{
+    error::Error,
+    thread,
+};
+
+use crate::{
+    Danmaku,
+    DanmakuQueue,
+};
+
+pub type LoadError = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadState {
+    Loading,
+    Done,
+}
+
+// Parses a track on a background thread and hands it over in batches, so
+// playback can start before a huge file is fully read.
+// Dropping the loader stops the parsing thread after its current batch.
+pub struct TrackLoader {
+    receiver: flume::Receiver<Result<Vec<Danmaku>, LoadError>>,
+}
+
+// Danmaku per batch for the built-in parsers
+#[cfg(feature = "bilibili")]
+const BATCH_SIZE: usize = 2048;
+
+impl TrackLoader {
+    pub fn spawn<I, E>(batches: I) -> Self
+    where
+        I: IntoIterator<Item = Result<Vec<Danmaku>, E>> + Send + 'static,
+        E: Into<LoadError>,
+    {
+        let (sender, receiver) = flume::unbounded();
+
+        thread::spawn(move || {
+            for batch in batches {
+                let batch = batch.map_err(Into::into);
+                let failed = batch.is_err();
+
+                if sender.send(batch).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Self { receiver }
+    }
+
+    // Bilibili XML, broken entries are skipped and legacy dumps repaired
+    #[cfg(feature = "bilibili")]
+    pub fn bilibili<R>(reader: R) -> Self
+    where
+        R: std::io::BufRead + Send + 'static,
+    {
+        let batches = crate::formats::bilibili::Parser::new()
+            .lenient(true)
+            .repair(true)
+            .batches(reader, BATCH_SIZE)
+            .map(|batch| batch.map(|parsed| parsed.danmaku));
+
+        Self::spawn(batches)
+    }
+
+    // Moves everything parsed so far into the queue without blocking.
+    // On error the danmaku loaded before it stay in the queue.
+    pub fn poll(&self, queue: &mut DanmakuQueue) -> Result<LoadState, LoadError> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(batch) => queue.extend(batch?),
+                Err(flume::TryRecvError::Empty) => return Ok(LoadState::Loading),
+                Err(flume::TryRecvError::Disconnected) => return Ok(LoadState::Done),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll_until_done(loader: &TrackLoader, queue: &mut DanmakuQueue) -> Result<(), LoadError> {
+        while loader.poll(queue)? == LoadState::Loading {
+            thread::yield_now();
+        }
+        Ok(())
+    }
+
+    fn starts(queue: &DanmakuQueue) -> Vec<f64> {
+        queue.danmaku().iter().map(|danmaku| danmaku.start).collect()
+    }
+
+    #[test]
+    fn test_loads_all_batches() {
+        let batches: Vec<Result<_, LoadError>> = vec![
+            Ok(vec![Danmaku::at("", 300.0), Danmaku::at("", 100.0)]),
+            Ok(vec![Danmaku::at("", 200.0)]),
+        ];
+        let loader = TrackLoader::spawn(batches);
+        let mut queue = DanmakuQueue::new();
+
+        poll_until_done(&loader, &mut queue).unwrap();
+        assert_eq!(starts(&queue), [100.0, 200.0, 300.0]);
+    }
+
+    #[test]
+    fn test_stops_at_error() {
+        let batches: Vec<Result<_, LoadError>> = vec![
+            Ok(vec![Danmaku::at("", 100.0)]),
+            Err("broken".into()),
+            Ok(vec![Danmaku::at("", 200.0)]),
+        ];
+        let loader = TrackLoader::spawn(batches);
+        let mut queue = DanmakuQueue::new();
+
+        let err = poll_until_done(&loader, &mut queue).unwrap_err();
+        assert_eq!(err.to_string(), "broken");
+        assert_eq!(starts(&queue), [100.0]);
+    }
+
+    #[cfg(feature = "bilibili")]
+    #[test]
+    fn test_bilibili() {
+        let xml = include_str!("../tests/fixtures/bilibili/basic.xml");
+        let loader = TrackLoader::bilibili(xml.as_bytes());
+        let mut queue = DanmakuQueue::new();
+
+        poll_until_done(&loader, &mut queue).unwrap();
+
+        let mut expected = crate::formats::bilibili::parse_str(xml).unwrap();
+        expected.sort_by(|a, b| a.start.total_cmp(&b.start));
+        assert_eq!(queue.danmaku(), expected);
+    }
+}
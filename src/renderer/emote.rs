@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use glyphon::{
+    ContentType,
+    CustomGlyphId,
+    RasterizeCustomGlyphRequest,
+    RasterizedCustomGlyph,
+};
+use thiserror::Error;
+
+// Shaped in place of an emote so it takes up one em of the emote's font size
+pub const EMOTE_PLACEHOLDER: &str = "\u{2003}";
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum EmoteError {
+    #[error("Expected {expected} bytes of RGBA data, got {actual}")]
+    InvalidImage { expected: usize, actual: usize },
+    #[error("Emote code must not be empty")]
+    EmptyCode,
+    #[error("Too many emotes registered")]
+    TooMany,
+}
+
+// What `EmoteRegistry::register` checks, for emotes stored before there is a registry
+pub fn check_emote(code: &str, width: u32, height: u32, rgba: &[u8]) -> Result<(), EmoteError> {
+    if code.is_empty() {
+        return Err(EmoteError::EmptyCode);
+    }
+
+    let expected = width as usize * height as usize * 4;
+    if rgba.len() != expected || expected == 0 {
+        return Err(EmoteError::InvalidImage {
+            expected,
+            actual: rgba.len(),
+        });
+    }
+
+    Ok(())
+}
+
+struct Emote {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    Emote(CustomGlyphId),
+}
+
+// Images that replace matching codes (e.g. `[doge]`) in danmaku text.
+// Ids are never reused so glyphs cached in the atlas can't go stale.
+#[derive(Default)]
+pub struct EmoteRegistry {
+    emotes: Vec<Option<Emote>>,
+    codes: HashMap<String, CustomGlyphId>,
+    // Longest first, so `[doge2]` wins over `[doge]`
+    sorted_codes: Vec<String>,
+}
+
+impl EmoteRegistry {
+    pub fn register(
+        &mut self, code: String, width: u32, height: u32, rgba: Vec<u8>,
+    ) -> Result<(), EmoteError> {
+        check_emote(&code, width, height, &rgba)?;
+
+        let id = CustomGlyphId::try_from(self.emotes.len()).map_err(|_| EmoteError::TooMany)?;
+
+        self.unregister(&code);
+        self.emotes.push(Some(Emote {
+            width,
+            height,
+            rgba,
+        }));
+        self.codes.insert(code.clone(), id);
+        self.sorted_codes.push(code);
+        self.sorted_codes
+            .sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, code: &str) {
+        let Some(id) = self.codes.remove(code) else {
+            return;
+        };
+
+        self.emotes[id as usize] = None;
+        self.sorted_codes.retain(|c| c != code);
+    }
+
+    pub fn clear(&mut self) {
+        self.emotes.iter_mut().for_each(|emote| *emote = None);
+        self.codes.clear();
+        self.sorted_codes.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    // Splits text into plain text and emotes, `None` when nothing matched
+    pub fn split<'a>(&self, text: &'a str) -> Option<Vec<Segment<'a>>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut segments = Vec::new();
+        let mut text_start = 0;
+        let mut index = 0;
+
+        while index < text.len() {
+            let rest = &text[index..];
+            let Some(code) = self.sorted_codes.iter().find(|code| rest.starts_with(*code)) else {
+                index += rest.chars().next().map_or(1, char::len_utf8);
+                continue;
+            };
+
+            if text_start < index {
+                segments.push(Segment::Text(&text[text_start..index]));
+            }
+            segments.push(Segment::Emote(self.codes[code]));
+
+            index += code.len();
+            text_start = index;
+        }
+
+        if segments.is_empty() {
+            return None;
+        }
+
+        if text_start < text.len() {
+            segments.push(Segment::Text(&text[text_start..]));
+        }
+
+        Some(segments)
+    }
+
+    // Nearest neighbour scaling of the source image to the requested size
+    pub fn rasterize(&self, request: RasterizeCustomGlyphRequest) -> Option<RasterizedCustomGlyph> {
+        let emote = self.emotes.get(request.id as usize)?.as_ref()?;
+        let (width, height) = (request.width as u32, request.height as u32);
+
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height {
+            let src_y = y * emote.height / height;
+            for x in 0..width {
+                let src_x = x * emote.width / width;
+                let offset = ((src_y * emote.width + src_x) * 4) as usize;
+                data.extend_from_slice(&emote.rgba[offset..offset + 4]);
+            }
+        }
+
+        Some(RasterizedCustomGlyph {
+            data,
+            content_type: ContentType::Color,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(codes: &[&str]) -> EmoteRegistry {
+        let mut registry = EmoteRegistry::default();
+        for code in codes {
+            registry
+                .register(code.to_string(), 1, 1, vec![255; 4])
+                .unwrap();
+        }
+        registry
+    }
+
+    #[test]
+    fn test_split_without_matches() {
+        assert_eq!(EmoteRegistry::default().split("[doge]"), None);
+        assert_eq!(registry(&["[doge]"]).split("no emotes here"), None);
+    }
+
+    #[test]
+    fn test_split_mixed_text() {
+        let registry = registry(&["[doge]", "Kappa"]);
+
+        assert_eq!(
+            registry.split("好[doge]耶Kappa"),
+            Some(vec![
+                Segment::Text("好"),
+                Segment::Emote(0),
+                Segment::Text("耶"),
+                Segment::Emote(1),
+            ])
+        );
+        assert_eq!(
+            registry.split("[doge][doge]"),
+            Some(vec![Segment::Emote(0), Segment::Emote(0)])
+        );
+    }
+
+    #[test]
+    fn test_split_prefers_longest_code() {
+        let registry = registry(&["[doge]", "[doge]]"]);
+        assert_eq!(
+            registry.split("[doge]]!"),
+            Some(vec![Segment::Emote(1), Segment::Text("!")])
+        );
+    }
+
+    #[test]
+    fn test_reregister_gets_new_id() {
+        let mut registry = registry(&["[doge]"]);
+        registry
+            .register("[doge]".to_string(), 1, 1, vec![0; 4])
+            .unwrap();
+
+        assert_eq!(registry.split("[doge]"), Some(vec![Segment::Emote(1)]));
+
+        registry.unregister("[doge]");
+        assert_eq!(registry.split("[doge]"), None);
+    }
+
+    #[test]
+    fn test_register_rejects_bad_image() {
+        let mut registry = EmoteRegistry::default();
+        assert_eq!(
+            registry.register("[doge]".to_string(), 2, 2, vec![0; 4]),
+            Err(EmoteError::InvalidImage {
+                expected: 16,
+                actual: 4
+            })
+        );
+        assert_eq!(
+            registry.register(String::new(), 1, 1, vec![0; 4]),
+            Err(EmoteError::EmptyCode)
+        );
+    }
+}
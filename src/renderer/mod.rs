@@ -1,9 +1,21 @@
+mod emote;
 mod render;
 
+pub use emote::EmoteError;
+pub(crate) use emote::check_emote;
 use render::RendererInner;
 use wgpu::TextureFormat;
 
-use crate::Danmaku;
+use crate::{
+    Danmaku,
+    DensityLimiter,
+    FilterSet,
+    LiveSource,
+    LoadError,
+    RepeatMerger,
+    TrackDurations,
+    TrackLoader,
+};
 
 pub struct Renderer(pub RendererInner);
 
@@ -30,9 +42,100 @@ impl Renderer {
     }
 
     pub fn init(&mut self, danmaku: Vec<Danmaku>) {
+        self.0.loader = None;
         self.0.danmaku_queue.init(danmaku, 0.0);
     }
 
+    // Swaps the track without replaying what is already past,
+    // e.g. after toggling a `TrackMerger` source
+    pub fn replace_danmaku(&mut self, danmaku: Vec<Danmaku>) {
+        self.0.loader = None;
+        self.0.danmaku_queue.replace(danmaku);
+    }
+
+    // Adds a comment to the track, e.g. one the user just sent. If its time
+    // has passed but it would still be on screen, it is shown right away.
+    pub fn insert_danmaku(&mut self, danmaku: Danmaku) {
+        let on_screen = self.0.durations.scroll.max(self.0.durations.center) as f64;
+        let show_now = danmaku.start > self.0.video_time - on_screen;
+
+        if !self.0.danmaku_queue.insert(danmaku.clone())
+            && show_now
+            && self.0.filters.allows(&danmaku)
+        {
+            self.0.add_text(danmaku);
+        }
+    }
+
+    // Retracts every comment with this id, including ones on screen
+    pub fn remove_danmaku(&mut self, id: u64) -> usize {
+        self.retain_danmaku(|danmaku| danmaku.meta.id != Some(id))
+    }
+
+    // Returns how many comments were removed from the track
+    pub fn retain_danmaku(&mut self, mut keep: impl FnMut(&Danmaku) -> bool) -> usize {
+        self.0.retain_visible(&mut keep);
+        self.0.danmaku_queue.retain(keep)
+    }
+
+    // Replaces the track with one that fills in while playing
+    pub fn load(&mut self, loader: TrackLoader) {
+        self.0.load_error = None;
+        self.0.danmaku_queue.init(Vec::new(), 0.0);
+        self.0.loader = Some(loader);
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.0.loader.is_some()
+    }
+
+    // Why the last load stopped early, the danmaku loaded until then are kept
+    pub fn take_load_error(&mut self) -> Option<LoadError> {
+        self.0.load_error.take()
+    }
+
+    // Danmaku already on screen are checked against the new filters
+    pub fn set_filters(&mut self, filters: FilterSet) {
+        self.0.filters = filters;
+        self.0.refilter_visible();
+    }
+
+    // Changes the filters in place, then re-checks what is on screen
+    pub fn edit_filters<T>(&mut self, edit: impl FnOnce(&mut FilterSet) -> T) -> T {
+        let result = edit(&mut self.0.filters);
+        self.0.refilter_visible();
+        result
+    }
+
+    // With how many danmaku each filter removed
+    pub fn filters(&self) -> &FilterSet {
+        &self.0.filters
+    }
+
+    pub fn take_filters(&mut self) -> FilterSet {
+        std::mem::take(&mut self.0.filters)
+    }
+
+    // Shows floods of the same comment once with a count, `None` shows each
+    pub fn set_repeat_merge(&mut self, merger: Option<RepeatMerger>) {
+        self.0.repeats = merger;
+    }
+
+    // Thins out busy scenes, `None` shows everything that fits
+    pub fn set_density_limit(&mut self, limiter: Option<DensityLimiter>) {
+        self.0.density = limiter;
+    }
+
+    // Shows comments pushed through the source's senders as they arrive,
+    // alongside the track
+    pub fn attach_live(&mut self, source: LiveSource) {
+        self.0.live = Some(source);
+    }
+
+    pub fn detach_live(&mut self) -> Option<LiveSource> {
+        self.0.live.take()
+    }
+
     pub fn update(&mut self, time_milis: f64) {
         self.0.update(time_milis);
     }
@@ -41,6 +144,26 @@ impl Renderer {
         self.0.add_text(danmaku);
     }
 
+    // Danmaku currently on screen, with their metadata
+    pub fn visible_danmaku(&self) -> impl Iterator<Item = &Danmaku> {
+        self.0.visible_danmaku()
+    }
+
+    // Replaces `code` in danmaku text with an RGBA image sized to the line height
+    pub fn register_emote(
+        &mut self, code: impl Into<String>, width: u32, height: u32, rgba: Vec<u8>,
+    ) -> Result<(), EmoteError> {
+        self.0.register_emote(code.into(), width, height, rgba)
+    }
+
+    pub fn unregister_emote(&mut self, code: &str) {
+        self.0.unregister_emote(code);
+    }
+
+    pub fn clear_emotes(&mut self) {
+        self.0.clear_emotes();
+    }
+
     pub fn set_font_name(&mut self, font_name: String) {
         self.0.font_name = font_name;
     }
@@ -62,8 +185,10 @@ impl Renderer {
 
     pub fn clear(&mut self) {
         self.0.scroll_danmaku.clear();
+        self.0.reverse_scroll_danmaku.clear();
         self.0.top_center_danmaku.clear();
         self.0.bottom_center_danmaku.clear();
+        self.0.positioned_danmaku.clear();
         self.0.top_center_row_occupied.fill(false);
         self.0.bottom_center_row_occupied.fill(false);
     }
@@ -72,6 +197,11 @@ impl Renderer {
         self.0.speed_factor = speed_factor;
     }
 
+    // Per track, e.g. `formats::niconico::DURATIONS` for Niconico comments
+    pub fn set_durations(&mut self, durations: TrackDurations) {
+        self.0.durations = durations;
+    }
+
     pub fn set_font_size(&mut self, font_size: f32) {
         self.0.font_size = font_size;
     }
@@ -1,20 +1,46 @@
+use std::{
+    borrow::Cow,
+    mem,
+    ptr,
+};
+
 use crate::{
     CenterDanmaku,
     Color,
     Danmaku,
     DanmakuMode,
     DanmakuQueue,
+    DensityLimiter,
+    FilterSet,
+    LiveSource,
+    LoadError,
+    LoadState,
+    PositionedDanmaku,
+    RepeatMerger,
     ScrollingDanmaku,
+    TextSpan,
+    TrackDurations,
+    TrackLoader,
+    layout::{
+        ScrollLane,
+        find_free_rows,
+        find_scroll_rows,
+        refit_free_rows,
+        refit_scroll_rows,
+    },
 };
 use glyphon::{
     Attrs,
     Buffer,
     Cache,
+    CustomGlyph,
+    CustomGlyphId,
     Family,
     FontSystem,
     Metrics,
     Resolution,
     Shaping,
+    Style,
     SwashCache,
     TextArea,
     TextAtlas,
@@ -65,10 +91,26 @@ use wgpu::{
     VertexState,
 };
 
+use super::emote::{
+    EMOTE_PLACEHOLDER,
+    EmoteError,
+    EmoteRegistry,
+    Segment,
+};
+
 pub struct RendererInner {
     pub danmaku_queue: DanmakuQueue,
+    pub loader: Option<TrackLoader>,
+    pub load_error: Option<LoadError>,
+    pub live: Option<LiveSource>,
+    pub filters: FilterSet,
+    pub density: Option<DensityLimiter>,
+    pub repeats: Option<RepeatMerger>,
     pub video_time: f64,
     pub video_speed: f64,
+    // Set while a seek replays the preroll, the filter counters are per playback
+    // so they skip what is hidden then
+    prerolling: bool,
 
     font_system: FontSystem,
     swash_cache: SwashCache,
@@ -84,6 +126,7 @@ pub struct RendererInner {
     pub paused: bool,
 
     pub scroll_danmaku: Vec<ScrollingDanmaku>,
+    pub reverse_scroll_danmaku: Vec<ScrollingDanmaku>,
     pub scroll_max_rows: usize,
 
     pub top_center_danmaku: Vec<CenterDanmaku>,
@@ -94,6 +137,8 @@ pub struct RendererInner {
     pub bottom_center_max_rows: usize,
     pub bottom_center_row_occupied: Vec<bool>,
 
+    pub positioned_danmaku: Vec<PositionedDanmaku>,
+
     pub line_height: f32,
     pub top_padding: f32,
     pub font_size: f32,
@@ -101,9 +146,12 @@ pub struct RendererInner {
     spacing: f32,
     pub scale_factor: f64,
     pub speed_factor: f64,
+    pub durations: TrackDurations,
 
     pub texture_view: Option<TextureView>,
     pub shadow: TextShadow,
+
+    emotes: EmoteRegistry,
 }
 
 
@@ -116,106 +164,186 @@ struct OffscreenLayer {
     height: u32,
 }
 
-const SCROLL_DURATION_MS: f32 = 8000.0;
-const CENTER_DURATION_MS: f32 = 5000.0;
 const RESET_DELTA_MS: f32 = 1000.0;
 const SEEK_PREROLL_STEP_MS: f64 = 50.0;
 const COMPOSITE_SHADER: &str = include_str!("shader.wgsl");
 
 impl RendererInner {
     pub fn add_scroll_danmaku(
-        &mut self, text_buffer: Buffer, width: f32, text_width: f32, danmaku: Danmaku,
-    ) {
-        let velocity_x = -(width + text_width) / SCROLL_DURATION_MS * self.speed_factor as f32;
-
-        let v = velocity_x.abs();
-
-        let mut found_row: Option<usize> = None;
-
-        let reach_edge_time = width / v;
-
-        for target_row in 0..self.scroll_max_rows {
-            let last_in_row = self
-                .scroll_danmaku
-                .iter()
-                .filter(|d| d.row == target_row)
-                .max_by(|a, b| a.x.partial_cmp(&b.x).unwrap_or(std::cmp::Ordering::Equal));
-
-            let Some(last_in_row) = last_in_row else {
-                found_row = Some(target_row);
-                break;
-            };
+        &mut self, text_buffer: Buffer, custom_glyphs: Vec<CustomGlyph>, width: f32,
+        text_width: f32, danmaku: Danmaku,
+    ) -> bool {
+        let velocity_x = -(width + text_width) / self.durations.scroll * self.speed_factor as f32;
+
+        let rows = danmaku.row_span();
+
+        let lanes = self.scroll_danmaku.iter().map(|d| ScrollLane {
+            row: d.row,
+            rows: d.rows,
+            distance: d.x + d.width,
+            width: d.width,
+            speed: d.velocity_x.abs(),
+        });
+        let Some(target_row) = find_scroll_rows(
+            lanes,
+            self.scroll_max_rows,
+            rows,
+            width,
+            velocity_x.abs(),
+            self.spacing,
+        ) else {
+            return false;
+        };
 
-            let leave_time =
-                (last_in_row.x + last_in_row.width + self.spacing) / last_in_row.velocity_x.abs();
+        self.scroll_danmaku.push(ScrollingDanmaku {
+            danmaku,
+            buffer: text_buffer,
+            custom_glyphs,
+            x: width,
+            row: target_row,
+            rows,
+            velocity_x,
+            width: text_width,
+        });
 
-            if leave_time < reach_edge_time
-                && width > last_in_row.width + self.spacing + last_in_row.x
-            {
-                found_row = Some(target_row);
-                break;
-            }
-        }
+        true
+    }
 
-        let Some(target_row) = found_row else {
-            return;
+    pub fn add_reverse_scroll_danmaku(
+        &mut self, text_buffer: Buffer, custom_glyphs: Vec<CustomGlyph>, width: f32,
+        text_width: f32, danmaku: Danmaku,
+    ) -> bool {
+        let velocity_x = (width + text_width) / self.durations.scroll * self.speed_factor as f32;
+
+        let rows = danmaku.row_span();
+
+        // Same rule as add_scroll_danmaku, measured from the left edge
+        let lanes = self.reverse_scroll_danmaku.iter().map(|d| ScrollLane {
+            row: d.row,
+            rows: d.rows,
+            distance: width - d.x,
+            width: d.width,
+            speed: d.velocity_x.abs(),
+        });
+        let Some(target_row) = find_scroll_rows(
+            lanes,
+            self.scroll_max_rows,
+            rows,
+            width,
+            velocity_x,
+            self.spacing,
+        ) else {
+            return false;
         };
 
-        self.scroll_danmaku.push(ScrollingDanmaku {
+        self.reverse_scroll_danmaku.push(ScrollingDanmaku {
             danmaku,
             buffer: text_buffer,
-            x: width,
+            custom_glyphs,
+            x: -text_width,
             row: target_row,
+            rows,
             velocity_x,
             width: text_width,
         });
+
+        true
     }
 
     pub fn add_topcenter_danmaku(
-        &mut self, text_buffer: Buffer, _width: f32, text_width: f32, danmaku: Danmaku,
-    ) {
-        let Some(target_row) = self
-            .top_center_row_occupied
-            .iter()
-            .position(|&occupied| !occupied)
-        else {
-            return;
+        &mut self, text_buffer: Buffer, custom_glyphs: Vec<CustomGlyph>, _width: f32,
+        text_width: f32, danmaku: Danmaku,
+    ) -> bool {
+        let rows = danmaku.row_span();
+
+        let Some(target_row) = find_free_rows(&self.top_center_row_occupied, rows) else {
+            return false;
         };
 
-        self.top_center_row_occupied[target_row] = true;
+        self.top_center_row_occupied[target_row..target_row + rows].fill(true);
 
         self.top_center_danmaku.push(CenterDanmaku {
             danmaku,
             buffer: text_buffer,
+            custom_glyphs,
             width: text_width,
             row: target_row,
-            remaining_time: CENTER_DURATION_MS,
+            rows,
+            remaining_time: self.durations.center,
         });
+
+        true
+    }
+
+    fn add_positioned_danmaku(
+        &mut self, text_buffer: Buffer, custom_glyphs: Vec<CustomGlyph>, text_width: f32,
+        danmaku: Danmaku,
+    ) -> bool {
+        let DanmakuMode::Positioned(spec) = &danmaku.mode else {
+            return false;
+        };
+
+        let (x, y) = spec.position_at(0.0);
+        let alpha = spec.alpha_at(0.0);
+
+        self.positioned_danmaku.push(PositionedDanmaku {
+            danmaku,
+            buffer: text_buffer,
+            custom_glyphs,
+            width: text_width,
+            elapsed: 0.0,
+            x,
+            y,
+            alpha,
+        });
+
+        true
     }
 
     fn add_bottomcenter_danmaku(
-        &mut self, text_buffer: Buffer, _width: f32, text_width: f32, danmaku: Danmaku,
-    ) {
-        let Some(target_row) = self
-            .bottom_center_row_occupied
-            .iter()
-            .position(|&occupied| !occupied)
-        else {
-            return;
+        &mut self, text_buffer: Buffer, custom_glyphs: Vec<CustomGlyph>, _width: f32,
+        text_width: f32, danmaku: Danmaku,
+    ) -> bool {
+        let rows = danmaku.row_span();
+
+        let Some(target_row) = find_free_rows(&self.bottom_center_row_occupied, rows) else {
+            return false;
         };
 
-        self.bottom_center_row_occupied[target_row] = true;
+        self.bottom_center_row_occupied[target_row..target_row + rows].fill(true);
 
         self.bottom_center_danmaku.push(CenterDanmaku {
             danmaku,
             buffer: text_buffer,
+            custom_glyphs,
             width: text_width,
             row: target_row,
-            remaining_time: CENTER_DURATION_MS,
+            rows,
+            remaining_time: self.durations.center,
         });
+
+        true
     }
 }
 
+fn span_attrs<'a>(base: &Attrs<'a>, span: &TextSpan) -> Attrs<'a> {
+    let mut attrs = base.clone();
+
+    if let Some(Color { r, g, b, a }) = span.color {
+        attrs = attrs.color(glyphon::Color::rgba(r, g, b, a));
+    }
+
+    if let Some(weight) = span.weight {
+        attrs = attrs.weight(Weight(weight));
+    }
+
+    if span.italic {
+        attrs = attrs.style(Style::Italic);
+    }
+
+    attrs
+}
+
 impl RendererInner {
     fn create_composite_resources(
         device: &wgpu::Device, format: TextureFormat,
@@ -372,8 +500,15 @@ impl RendererInner {
         Self {
             font_name: String::new(),
             danmaku_queue: DanmakuQueue::new(),
+            loader: None,
+            load_error: None,
+            live: None,
+            filters: FilterSet::new(),
+            density: None,
+            repeats: None,
             video_time: 0.0,
             video_speed: 1.0,
+            prerolling: false,
             font_system,
             swash_cache,
             viewport,
@@ -385,8 +520,10 @@ impl RendererInner {
             composite_bind_group_layout,
             composite_pipeline,
             scroll_danmaku: Vec::new(),
+            reverse_scroll_danmaku: Vec::new(),
             top_center_danmaku: Vec::new(),
             bottom_center_danmaku: Vec::new(),
+            positioned_danmaku: Vec::new(),
             scroll_max_rows,
             top_center_max_rows,
             bottom_center_max_rows,
@@ -395,29 +532,122 @@ impl RendererInner {
             font_size,
             scale_factor,
             speed_factor,
+            durations: TrackDurations::default(),
             top_center_row_occupied,
             bottom_center_row_occupied,
             paused: false,
             spacing,
             texture_view: None,
             shadow,
+            emotes: EmoteRegistry::default(),
         }
     }
 
-    pub fn add_text(&mut self, danmaku: Danmaku) {
-        let font_size = self.font_size;
-        let metrics = Metrics::new(font_size, self.line_height);
+    // Whether the danmaku found room on screen
+    pub fn add_text(&mut self, danmaku: Danmaku) -> bool {
+        let (text_buffer, custom_glyphs, text_width) = self.shape(&danmaku);
+        let width = self.viewport.resolution().width as f32;
+
+        match danmaku.mode {
+            DanmakuMode::Scroll => {
+                self.add_scroll_danmaku(text_buffer, custom_glyphs, width, text_width, danmaku)
+            }
+            DanmakuMode::ReverseScroll => self.add_reverse_scroll_danmaku(
+                text_buffer,
+                custom_glyphs,
+                width,
+                text_width,
+                danmaku,
+            ),
+            DanmakuMode::TopCenter => {
+                self.add_topcenter_danmaku(text_buffer, custom_glyphs, width, text_width, danmaku)
+            }
+            DanmakuMode::BottomCenter => self.add_bottomcenter_danmaku(
+                text_buffer,
+                custom_glyphs,
+                width,
+                text_width,
+                danmaku,
+            ),
+            DanmakuMode::Positioned(_) => {
+                self.add_positioned_danmaku(text_buffer, custom_glyphs, text_width, danmaku)
+            }
+        }
+    }
+
+    // Lays out the text, returning the buffer, the emotes to draw over it and its width
+    fn shape(&mut self, danmaku: &Danmaku) -> (Buffer, Vec<CustomGlyph>, f32) {
+        let scale = danmaku.scale();
+        let metrics = Metrics::new(self.font_size * scale, self.line_height * scale);
         let mut text_buffer = Buffer::new(&mut self.font_system, metrics);
+        let font_name = match &danmaku.mode {
+            DanmakuMode::Positioned(spec) => spec.font.as_deref(),
+            _ => None,
+        }
+        .unwrap_or(&self.font_name);
         let text_attrs = Attrs::new()
-            .family(Family::Name(&self.font_name))
+            .family(Family::Name(font_name))
             .weight(Weight::NORMAL);
 
-        text_buffer.set_text(
-            &mut self.font_system,
-            &danmaku.content,
-            &text_attrs,
-            Shaping::Advanced,
-        );
+        // Merged repeats are shown once, followed by their count
+        let repeat_suffix = danmaku
+            .repeats
+            .filter(|&count| count > 1)
+            .map(|count| format!(" ×{count}"));
+
+        let mut spans: Vec<(&str, Attrs)> = match &danmaku.spans {
+            Some(spans) => spans
+                .iter()
+                .map(|span| (span.text.as_str(), span_attrs(&text_attrs, span)))
+                .collect(),
+            None => vec![(danmaku.content.as_str(), text_attrs.clone())],
+        };
+        if let Some(suffix) = &repeat_suffix {
+            spans.push((suffix.as_str(), text_attrs.clone()));
+        }
+
+        // Emotes are shaped as a placeholder as wide as the line is high and
+        // tagged with their id, the image is drawn over it as a custom glyph
+        let emote_metrics = Metrics::new(self.line_height * scale, self.line_height * scale);
+        let mut has_emotes = false;
+        let mut rich_spans = Vec::with_capacity(spans.len());
+        for (text, attrs) in spans {
+            let Some(segments) = self.emotes.split(text) else {
+                rich_spans.push((text, attrs));
+                continue;
+            };
+
+            has_emotes = true;
+            for segment in segments {
+                match segment {
+                    Segment::Text(text) => rich_spans.push((text, attrs.clone())),
+                    Segment::Emote(id) => rich_spans.push((
+                        EMOTE_PLACEHOLDER,
+                        attrs
+                            .clone()
+                            .metrics(emote_metrics)
+                            .metadata(id as usize + 1),
+                    )),
+                }
+            }
+        }
+
+        if danmaku.spans.is_some() || has_emotes || repeat_suffix.is_some() {
+            text_buffer.set_rich_text(
+                &mut self.font_system,
+                rich_spans,
+                &text_attrs,
+                Shaping::Advanced,
+                None,
+            );
+        } else {
+            text_buffer.set_text(
+                &mut self.font_system,
+                &danmaku.content,
+                &text_attrs,
+                Shaping::Advanced,
+            );
+        }
 
         let text_width = text_buffer
             .layout_runs()
@@ -425,34 +655,232 @@ impl RendererInner {
             .reduce(f32::max)
             .unwrap_or(0.0);
 
-        let width = self.viewport.resolution().width as f32;
+        let custom_glyphs = if has_emotes {
+            text_buffer
+                .layout_runs()
+                .flat_map(|run| {
+                    run.glyphs
+                        .iter()
+                        .filter(|glyph| glyph.metadata != 0)
+                        .map(move |glyph| CustomGlyph {
+                            id: (glyph.metadata - 1) as CustomGlyphId,
+                            left: glyph.x,
+                            top: run.line_top,
+                            width: emote_metrics.line_height,
+                            height: emote_metrics.line_height,
+                            color: None,
+                            snap_to_physical_pixel: true,
+                            metadata: 0,
+                        })
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
 
-        match danmaku.mode {
-            DanmakuMode::Scroll => {
-                self.add_scroll_danmaku(text_buffer, width, text_width, danmaku);
+        (text_buffer, custom_glyphs, text_width)
+    }
+
+    // Swaps in a new version of a danmaku on screen, matched by start and
+    // content, e.g. with a higher repeat count. It keeps its place unless it
+    // grew out of it, if there is no room left it stays as it was. Returns
+    // whether it was still on screen.
+    fn update_visible(&mut self, danmaku: Danmaku) -> bool {
+        let (buffer, custom_glyphs, width) = self.shape(&danmaku);
+        let rows = danmaku.row_span();
+        let same =
+            |other: &Danmaku| other.start == danmaku.start && other.content == danmaku.content;
+
+        for reverse in [false, true] {
+            let texts = if reverse {
+                &self.reverse_scroll_danmaku
+            } else {
+                &self.scroll_danmaku
+            };
+            let Some(index) = texts.iter().position(|text| same(&text.danmaku)) else {
+                continue;
+            };
+
+            if let Some((row, x)) = self.refit_scroll(reverse, index, rows, width) {
+                let text = if reverse {
+                    &mut self.reverse_scroll_danmaku[index]
+                } else {
+                    &mut self.scroll_danmaku[index]
+                };
+                (text.row, text.rows, text.x) = (row, rows, x);
+                text.buffer = buffer;
+                text.custom_glyphs = custom_glyphs;
+                text.width = width;
+                text.danmaku = danmaku;
             }
-            DanmakuMode::TopCenter => {
-                self.add_topcenter_danmaku(text_buffer, width, text_width, danmaku);
+            return true;
+        }
+
+        for (texts, occupied) in [
+            (
+                &mut self.top_center_danmaku,
+                &mut self.top_center_row_occupied,
+            ),
+            (
+                &mut self.bottom_center_danmaku,
+                &mut self.bottom_center_row_occupied,
+            ),
+        ] {
+            let Some(text) = texts.iter_mut().find(|text| same(&text.danmaku)) else {
+                continue;
+            };
+
+            let old_rows = text.row..text.row + text.rows;
+            if let Some(occupied) = occupied.get_mut(old_rows.clone()) {
+                occupied.fill(false);
             }
-            DanmakuMode::BottomCenter => {
-                self.add_bottomcenter_danmaku(text_buffer, width, text_width, danmaku);
+            match refit_free_rows(occupied, text.row, rows) {
+                Some(row) => {
+                    occupied[row..row + rows].fill(true);
+                    (text.row, text.rows) = (row, rows);
+                    text.buffer = buffer;
+                    text.custom_glyphs = custom_glyphs;
+                    text.width = width;
+                    text.danmaku = danmaku;
+                }
+                None => {
+                    if let Some(occupied) = occupied.get_mut(old_rows) {
+                        occupied.fill(true);
+                    }
+                }
             }
+            return true;
         }
+
+        if let Some(text) = self
+            .positioned_danmaku
+            .iter_mut()
+            .find(|text| same(&text.danmaku))
+        {
+            text.buffer = buffer;
+            text.custom_glyphs = custom_glyphs;
+            text.width = width;
+            text.danmaku = danmaku;
+            return true;
+        }
+
+        false
+    }
+
+    // Row and x for a scrolling danmaku on screen after its text changed to
+    // `rows` and `width`. The leading edge stays put, the text grows behind it.
+    fn refit_scroll(
+        &self, reverse: bool, index: usize, rows: usize, width: f32,
+    ) -> Option<(usize, f32)> {
+        let screen_width = self.viewport.resolution().width as f32;
+        let texts = if reverse {
+            &self.reverse_scroll_danmaku
+        } else {
+            &self.scroll_danmaku
+        };
+        // Same lanes as add_scroll_danmaku and add_reverse_scroll_danmaku
+        let distance = move |x: f32, width: f32| if reverse { screen_width - x } else { x + width };
+
+        let text = &texts[index];
+        let x = if reverse {
+            text.x + text.width - width
+        } else {
+            text.x
+        };
+        let lane = ScrollLane {
+            row: text.row,
+            rows,
+            distance: distance(x, width),
+            width,
+            speed: text.velocity_x.abs(),
+        };
+        let others = texts
+            .iter()
+            .enumerate()
+            .filter(move |&(other, _)| other != index)
+            .map(move |(_, d)| ScrollLane {
+                row: d.row,
+                rows: d.rows,
+                distance: distance(d.x, d.width),
+                width: d.width,
+                speed: d.velocity_x.abs(),
+            });
+
+        refit_scroll_rows(others, self.scroll_max_rows, lane, self.spacing).map(|row| (row, x))
+    }
+
+    pub fn visible_danmaku(&self) -> impl Iterator<Item = &Danmaku> {
+        let scroll = self.scroll_danmaku.iter().map(|text| &text.danmaku);
+        let reverse_scroll = self.reverse_scroll_danmaku.iter().map(|text| &text.danmaku);
+        let top_center = self.top_center_danmaku.iter().map(|text| &text.danmaku);
+        let bottom_center = self.bottom_center_danmaku.iter().map(|text| &text.danmaku);
+        let positioned = self.positioned_danmaku.iter().map(|text| &text.danmaku);
+
+        scroll
+            .chain(reverse_scroll)
+            .chain(top_center)
+            .chain(bottom_center)
+            .chain(positioned)
+    }
+
+    // Takes danmaku off the screen, e.g. after they were retracted
+    pub fn retain_visible(&mut self, mut keep: impl FnMut(&Danmaku) -> bool) {
+        self.scroll_danmaku.retain(|text| keep(&text.danmaku));
+        self.reverse_scroll_danmaku
+            .retain(|text| keep(&text.danmaku));
+
+        self.top_center_danmaku.retain(|text| {
+            if keep(&text.danmaku) {
+                return true;
+            }
+            if let Some(occupied) = self
+                .top_center_row_occupied
+                .get_mut(text.row..text.row + text.rows)
+            {
+                occupied.fill(false);
+            }
+            false
+        });
+
+        self.bottom_center_danmaku.retain(|text| {
+            if keep(&text.danmaku) {
+                return true;
+            }
+            if let Some(occupied) = self
+                .bottom_center_row_occupied
+                .get_mut(text.row..text.row + text.rows)
+            {
+                occupied.fill(false);
+            }
+            false
+        });
+
+        self.positioned_danmaku.retain(|text| keep(&text.danmaku));
+    }
+
+    // Hides danmaku on screen that the current filters block
+    pub fn refilter_visible(&mut self) {
+        let mut filters = mem::take(&mut self.filters);
+        self.retain_visible(|danmaku| filters.allows(danmaku));
+        self.filters = filters;
     }
 
     pub fn rebuild_visible_state_at(&mut self, time_milis: f64) {
-        let preroll_ms = SCROLL_DURATION_MS.max(CENTER_DURATION_MS) as f64;
+        let preroll_ms = self.durations.scroll.max(self.durations.center) as f64;
         let start_time = (time_milis - preroll_ms).max(0.0);
 
         self.scroll_danmaku.clear();
+        self.reverse_scroll_danmaku.clear();
         self.top_center_danmaku.clear();
         self.bottom_center_danmaku.clear();
+        self.positioned_danmaku.clear();
         self.top_center_row_occupied.fill(false);
         self.bottom_center_row_occupied.fill(false);
 
         self.danmaku_queue.reset_time(start_time);
         self.video_time = start_time;
 
+        self.prerolling = true;
         let mut simulated_time = start_time;
         while simulated_time + SEEK_PREROLL_STEP_MS < time_milis {
             simulated_time += SEEK_PREROLL_STEP_MS;
@@ -460,9 +888,13 @@ impl RendererInner {
         }
 
         self.update(time_milis);
+        self.prerolling = false;
     }
 
     pub fn update(&mut self, time_milis: f64) {
+        self.poll_loader();
+        self.poll_live();
+
         let delta_time = (time_milis - self.video_time) as f32;
         self.video_time = time_milis;
 
@@ -471,9 +903,25 @@ impl RendererInner {
             return;
         }
 
-        for next_danmaku in self.danmaku_queue.pop_to_time(self.video_time) {
-            self.add_text(next_danmaku);
-        }
+        // Taken out so the popped danmaku can be borrowed while adding them
+        let mut queue = mem::take(&mut self.danmaku_queue);
+        let popped = queue.pop_to_time(self.video_time).len();
+        let past = queue.past();
+        let due: Vec<&Danmaku> = past[past.len() - popped..]
+            .iter()
+            .filter(|danmaku| {
+                if self.prerolling {
+                    self.filters.allows_uncounted(danmaku)
+                } else {
+                    self.filters.allows(danmaku)
+                }
+            })
+            .collect();
+        let due = self.merge_repeats(due, queue.upcoming());
+        let due: Vec<&Danmaku> = due.iter().map(AsRef::as_ref).collect();
+        let kept = self.limit_density(due.clone(), queue.upcoming());
+        self.add_merged(due, kept);
+        self.danmaku_queue = queue;
 
         for text in self.scroll_danmaku.iter_mut() {
             text.x += text.velocity_x * delta_time * self.speed_factor as f32;
@@ -481,14 +929,24 @@ impl RendererInner {
 
         self.scroll_danmaku.retain(|text| text.x + text.width > 0.0);
 
+        for text in self.reverse_scroll_danmaku.iter_mut() {
+            text.x += text.velocity_x * delta_time * self.speed_factor as f32;
+        }
+
+        let width = self.viewport.resolution().width as f32;
+        self.reverse_scroll_danmaku.retain(|text| text.x < width);
+
         for text in self.top_center_danmaku.iter_mut() {
             text.remaining_time -= delta_time;
         }
 
         self.top_center_danmaku.retain(|text| {
             if text.remaining_time <= 0.0 {
-                if let Some(occupied) = self.top_center_row_occupied.get_mut(text.row) {
-                    *occupied = false;
+                if let Some(occupied) = self
+                    .top_center_row_occupied
+                    .get_mut(text.row..text.row + text.rows)
+                {
+                    occupied.fill(false);
                 }
                 false
             } else {
@@ -502,14 +960,136 @@ impl RendererInner {
 
         self.bottom_center_danmaku.retain(|text| {
             if text.remaining_time <= 0.0 {
-                if let Some(occupied) = self.bottom_center_row_occupied.get_mut(text.row) {
-                    *occupied = false;
+                if let Some(occupied) = self
+                    .bottom_center_row_occupied
+                    .get_mut(text.row..text.row + text.rows)
+                {
+                    occupied.fill(false);
                 }
                 false
             } else {
                 true
             }
         });
+
+        for text in self.positioned_danmaku.iter_mut() {
+            text.elapsed += delta_time;
+
+            let DanmakuMode::Positioned(spec) = &text.danmaku.mode else {
+                continue;
+            };
+
+            (text.x, text.y) = spec.position_at(text.elapsed);
+            text.alpha = spec.alpha_at(text.elapsed);
+        }
+
+        self.positioned_danmaku.retain(|text| match &text.danmaku.mode {
+            DanmakuMode::Positioned(spec) => text.elapsed < spec.duration,
+            _ => false,
+        });
+    }
+
+    fn poll_loader(&mut self) {
+        let Some(loader) = &self.loader else {
+            return;
+        };
+
+        match loader.poll(&mut self.danmaku_queue) {
+            Ok(LoadState::Loading) => {}
+            Ok(LoadState::Done) => self.loader = None,
+            Err(e) => {
+                self.load_error = Some(e);
+                self.loader = None;
+            }
+        }
+    }
+
+    fn poll_live(&mut self) {
+        let Some(live) = self.live.take() else {
+            return;
+        };
+
+        let arrived: Vec<Danmaku> = live
+            .drain()
+            .filter(|danmaku| self.filters.allows(danmaku))
+            .collect();
+        self.live = Some(live);
+
+        // Windows are keyed on when live danmaku arrive, repeats of one still
+        // on screen count up its ×N
+        let (arrived, counted) = match &mut self.repeats {
+            Some(merger) => merger.merge_live(arrived, self.video_time),
+            None => (arrived, Vec::new()),
+        };
+        for danmaku in counted {
+            // Gone already, e.g. retracted, so later repeats start over
+            if !self.update_visible(danmaku.clone()) {
+                if let Some(merger) = &mut self.repeats {
+                    merger.forget(&danmaku);
+                }
+            }
+        }
+        let kept = self.limit_density(arrived.iter().collect(), &[]);
+        self.add_merged(arrived.iter().collect(), kept);
+    }
+
+    // Adds the `kept` danmaku, a subset of `merged` in the same order. The
+    // repeat windows of the others are forgotten, their count would be shown
+    // nowhere.
+    fn add_merged(&mut self, merged: Vec<&Danmaku>, kept: Vec<&Danmaku>) {
+        let mut kept = kept.into_iter().peekable();
+        for danmaku in merged {
+            let shown = kept.next_if(|&kept| ptr::eq(kept, danmaku)).is_some()
+                && self.add_text(danmaku.clone());
+            if !shown {
+                if let Some(merger) = &mut self.repeats {
+                    merger.forget(danmaku);
+                }
+            }
+        }
+    }
+
+    fn merge_repeats<'a>(
+        &mut self, due: Vec<&'a Danmaku>, upcoming: &[Danmaku],
+    ) -> Vec<Cow<'a, Danmaku>> {
+        let filters = &self.filters;
+        match &mut self.repeats {
+            Some(merger) => merger.merge(due, upcoming, self.video_time, |danmaku| {
+                filters.allows_uncounted(danmaku)
+            }),
+            None => due.into_iter().map(Cow::Borrowed).collect(),
+        }
+    }
+
+    fn limit_density<'a>(
+        &mut self, due: Vec<&'a Danmaku>, upcoming: &[Danmaku],
+    ) -> Vec<&'a Danmaku> {
+        let on_screen = self.visible_danmaku().count();
+        let (filters, repeats) = (&self.filters, &self.repeats);
+        let shown = |danmaku: &Danmaku| {
+            filters.allows_uncounted(danmaku)
+                && !repeats
+                    .as_ref()
+                    .is_some_and(|merger| merger.absorbs(danmaku))
+        };
+        match &mut self.density {
+            Some(limiter) => limiter.sample(due, upcoming, self.video_time, on_screen, shown),
+            None => due,
+        }
+    }
+
+    pub fn register_emote(
+        &mut self, code: String, width: u32, height: u32, rgba: Vec<u8>,
+    ) -> Result<(), EmoteError> {
+        self.emotes.register(code, width, height, rgba)
+    }
+
+    pub fn unregister_emote(&mut self, code: &str) {
+        self.emotes.unregister(code);
+    }
+
+    pub fn clear_emotes(&mut self) {
+        self.emotes.clear();
     }
 
     pub fn resize(&mut self, queue: &wgpu::Queue, width: u32, height: u32) {
@@ -544,7 +1124,22 @@ impl RendererInner {
                 scale: 1.0,
                 bounds,
                 default_color: glyphon::Color::rgba(r, g, b, a),
-                custom_glyphs: &[],
+                custom_glyphs: &text.custom_glyphs,
+                shadow: Some(self.shadow),
+            }
+        });
+
+        let reverse_scroll_areas = self.reverse_scroll_danmaku.iter_mut().map(|text| {
+            let top_y = self.top_padding + (text.row as f32 * self.line_height);
+            let Color { r, g, b, a } = text.danmaku.color;
+            TextArea {
+                buffer: &mut text.buffer,
+                left: text.x,
+                top: top_y,
+                scale: 1.0,
+                bounds,
+                default_color: glyphon::Color::rgba(r, g, b, a),
+                custom_glyphs: &text.custom_glyphs,
                 shadow: Some(self.shadow),
             }
         });
@@ -558,7 +1153,7 @@ impl RendererInner {
                 scale: 1.0,
                 bounds,
                 default_color: glyphon::Color::rgba(r, g, b, a),
-                custom_glyphs: &[],
+                custom_glyphs: &text.custom_glyphs,
                 shadow: Some(self.shadow),
             }
         });
@@ -568,21 +1163,41 @@ impl RendererInner {
             TextArea {
                 buffer: &mut text.buffer,
                 left: (width as f32 - text.width) / 2.0,
-                top: height as f32 - self.top_padding - ((text.row + 1) as f32 * self.line_height),
+                top: height as f32
+                    - self.top_padding
+                    - ((text.row + text.rows) as f32 * self.line_height),
+                scale: 1.0,
+                bounds,
+                default_color: glyphon::Color::rgba(r, g, b, a),
+                custom_glyphs: &text.custom_glyphs,
+                shadow: Some(self.shadow),
+            }
+        });
+
+        let positioned_areas = self.positioned_danmaku.iter_mut().map(|text| {
+            let Color { r, g, b, a } = text.danmaku.color;
+            let a = (a as f32 * text.alpha.clamp(0.0, 1.0)) as u8;
+            TextArea {
+                buffer: &mut text.buffer,
+                left: text.x * width as f32,
+                top: text.y * height as f32,
                 scale: 1.0,
                 bounds,
                 default_color: glyphon::Color::rgba(r, g, b, a),
-                custom_glyphs: &[],
+                custom_glyphs: &text.custom_glyphs,
                 shadow: Some(self.shadow),
             }
         });
 
         let areas = scroll_areas
+            .chain(reverse_scroll_areas)
             .chain(top_center_areas)
-            .chain(bottom_center_areas);
+            .chain(bottom_center_areas)
+            .chain(positioned_areas);
 
+        let emotes = &self.emotes;
         self.text_renderer
-            .prepare(
+            .prepare_with_custom(
                 device,
                 queue,
                 &mut self.font_system,
@@ -590,6 +1205,7 @@ impl RendererInner {
                 &self.viewport,
                 areas,
                 &mut self.swash_cache,
+                |request| emotes.rasterize(request),
             )
             .unwrap();
 